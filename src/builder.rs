@@ -0,0 +1,332 @@
+//! Builds diagrams from named sets, so that maps refer to sets by name instead of by index
+//!
+//! ```
+//! use commuter::builder::DiagramBuilder;
+//! use commuter::diagram::{Set, diagram_commutes, CommutativeDiagramResult};
+//!
+//! let diagram = DiagramBuilder::new()
+//!     .set("pairs", Set::new(vec![(1, 2), (3, 4)]))
+//!     .set("integers", Set::<i32, _, _>::new_no_generating_set())
+//!     .map("pairs", "integers", |(a, b): &(i32, i32)| a + b, "a + b")
+//!     .map("pairs", "integers", |(a, b): &(i32, i32)| b + a, "b + a")
+//!     .build()
+//!     .unwrap();
+//!
+//! assert!(matches!(
+//!     diagram_commutes(&diagram).unwrap(),
+//!     CommutativeDiagramResult::Commutes { .. }
+//! ));
+//! ```
+//!
+//! Alternatively, adding a set as an object hands out a typed handle, so that maps between objects are type checked:
+//!
+//! ```
+//! use commuter::builder::DiagramBuilder;
+//! use commuter::diagram::Set;
+//!
+//! let mut builder = DiagramBuilder::new();
+//! let pairs = builder.object("pairs", Set::new(vec![(1, 2), (3, 4)]));
+//! let integers = builder.object("integers", Set::<i32, _, _>::new_no_generating_set());
+//! builder.arrow(&pairs, &integers, |(a, b): &(i32, i32)| a + b, "a + b");
+//! let diagram = builder.build().unwrap();
+//! ```
+//!
+//! A map whose domain doesn't match its source object is then rejected at compile time:
+//!
+//! ```compile_fail
+//! use commuter::builder::DiagramBuilder;
+//! use commuter::diagram::Set;
+//!
+//! let mut builder = DiagramBuilder::new();
+//! let pairs = builder.object("pairs", Set::new(vec![(1, 2), (3, 4)]));
+//! let integers = builder.object("integers", Set::<i32, _, _>::new_no_generating_set());
+//! builder.arrow(&integers, &pairs, |(a, b): &(i32, i32)| a + b, "a + b");
+//! ```
+
+use crate::diagram::{Diagram, DiagramError, Element, Map, Set, SetLike};
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+/// A set whose elements have a known type, so that it can be added to a diagram as a typed object
+pub trait TypedSet: SetLike {
+    type Element: Clone + Element + PartialEq + core::fmt::Debug + 'static;
+}
+
+impl<T, P, F, S, O, E, N> TypedSet for Set<T, P, F, S, O, E, N>
+where
+    T: Clone + Element + PartialEq + core::fmt::Debug + 'static,
+    P: Fn(&T) -> bool,
+    F: Fn(&T) -> bool,
+    S: Fn(&T) -> Box<dyn Iterator<Item = T>>,
+    O: Fn(&T, &T) -> bool,
+    E: Fn(&T, &T) -> bool,
+    N: Fn(&T) -> Rc<dyn Element>,
+{
+    type Element = T;
+}
+
+/// A handle to a set of elements of type T in a diagram under construction, see `DiagramBuilder::object`
+pub struct Obj<T> {
+    name: String,
+    elements: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for Obj<T> {
+    fn clone(&self) -> Self {
+        Obj {
+            name: self.name.clone(),
+            elements: PhantomData,
+        }
+    }
+}
+
+impl<T> Obj<T> {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+#[derive(Default)]
+pub struct DiagramBuilder {
+    sets: Vec<(String, Rc<dyn SetLike>)>,
+    maps: Vec<(String, String, Map)>, // (From, To, Map) - the indices in the map are resolved when building
+}
+
+impl DiagramBuilder {
+    pub fn new() -> DiagramBuilder {
+        DiagramBuilder::default()
+    }
+
+    pub fn set(&mut self, name: &str, set: Rc<dyn SetLike>) -> &mut DiagramBuilder {
+        self.sets.push((name.to_owned(), set));
+        self
+    }
+
+    // Adds a set and returns a typed handle to it, to be used with `arrow`
+    pub fn object<S>(&mut self, name: &str, set: Rc<S>) -> Obj<S::Element>
+    where
+        S: TypedSet + 'static,
+    {
+        self.sets.push((name.to_owned(), set));
+        Obj {
+            name: name.to_owned(),
+            elements: PhantomData,
+        }
+    }
+
+    // Adds a map between two objects, which only compiles if it maps the elements of one to those of the other
+    pub fn arrow<F, U, V>(
+        &mut self,
+        from: &Obj<U>,
+        to: &Obj<V>,
+        map: F,
+        name: &str,
+    ) -> &mut DiagramBuilder
+    where
+        F: Fn(&U) -> V + 'static + Clone,
+        U: Clone + PartialEq + core::fmt::Debug + 'static,
+        V: Clone + PartialEq + core::fmt::Debug + 'static,
+    {
+        self.maps.push((
+            from.name.clone(),
+            to.name.clone(),
+            Map::new(0, 0, map, name),
+        ));
+        self
+    }
+
+    pub fn map<F, U, V>(&mut self, from: &str, to: &str, map: F, name: &str) -> &mut DiagramBuilder
+    where
+        F: Fn(&U) -> V + 'static + Clone,
+        U: Clone + PartialEq + core::fmt::Debug + 'static,
+        V: Clone + PartialEq + core::fmt::Debug + 'static,
+    {
+        self.maps
+            .push((from.to_owned(), to.to_owned(), Map::new(0, 0, map, name)));
+        self
+    }
+
+    // Builds the diagram, checking that set names are unique and that every map refers to existing sets of the right types.
+    // The sets and maps are moved into the diagram, so the builder is left empty
+    pub fn build(&mut self) -> Result<Diagram, DiagramError> {
+        let DiagramBuilder { sets, maps } = std::mem::take(self);
+
+        let names: Vec<String> = sets.iter().map(|(name, _)| name.clone()).collect();
+        for (ix, name) in names.iter().enumerate() {
+            if names[..ix].contains(name) {
+                return Err(DiagramError::DuplicateSet(name.clone()));
+            }
+        }
+
+        let index_of = |map: &Map, set: &str| {
+            names
+                .iter()
+                .position(|name| name == set)
+                .ok_or_else(|| DiagramError::UnknownSet {
+                    map: map.name.clone(),
+                    set: set.to_owned(),
+                })
+        };
+
+        let mut resolved = Vec::new();
+        for (from, to, mut map) in maps {
+            map.from = index_of(&map, &from)?;
+            map.to = index_of(&map, &to)?;
+            resolved.push(map);
+        }
+
+        let name_refs: Vec<&str> = names.iter().map(|name| name.as_str()).collect();
+        let diagram = Diagram::new(sets.into_iter().map(|(_, set)| set).collect(), resolved)
+            .with_set_names(&name_refs);
+
+        // Validated after naming, so that errors refer to the sets by name
+        diagram.validate()?;
+        Ok(diagram)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagram::{
+        diagram_commutes, CommutativeDiagramError, CommutativeDiagramResult, Set,
+    };
+
+    #[test]
+    fn test_maps_refer_to_sets_by_name() {
+        // The sets are added in a different order than they are used, which would silently break index-based maps
+        let diagram = DiagramBuilder::new()
+            .set("Z", Set::<i32, _, _>::new_no_generating_set())
+            .set("N", Set::new((0..10).collect::<Vec<u32>>()))
+            .map("N", "Z", |x: &u32| *x as i32, "as_i32")
+            .map("N", "Z", |x: &u32| -(*x as i32), "negate")
+            .build()
+            .unwrap();
+
+        match diagram_commutes(&diagram).unwrap() {
+            CommutativeDiagramResult::Commutes { .. }
+            | CommutativeDiagramResult::Incomplete { .. } => {
+                panic!("negation is not the inclusion")
+            }
+            CommutativeDiagramResult::DoesNotCommute(counterexamples) => {
+                assert!(counterexamples[0]
+                    .to_string()
+                    .starts_with("as_i32 and negate from N to Z don't agree on 1"));
+            }
+        }
+
+        let diagram = DiagramBuilder::new()
+            .set("N", Set::new((0..10).collect::<Vec<u32>>()))
+            .set(
+                "Z",
+                Set::<i32, _, _>::new_no_generating_set_checked(|x: &i32| *x < 5),
+            )
+            .map("N", "Z", |x: &u32| *x as i32, "as_i32")
+            .build()
+            .unwrap();
+
+        match diagram_commutes(&diagram) {
+            Err(CommutativeDiagramError::PropertyCheckError(violation)) => {
+                assert_eq!(violation.set_name, "Z");
+                assert!(violation
+                    .to_string()
+                    .starts_with("Element does not satisfy property of target set Z: \"5\""));
+            }
+            _ => panic!("5 is not below 5"),
+        }
+    }
+
+    #[test]
+    fn test_checked_elements_are_named() {
+        let diagram = DiagramBuilder::new()
+            .set("A", Set::new(vec![1, 2, 3]))
+            .set("B", Set::<i32, _, _>::new_no_generating_set())
+            .map("A", "B", |x: &i32| x + 1, "succ")
+            .build()
+            .unwrap();
+
+        match diagram_commutes(&diagram).unwrap() {
+            CommutativeDiagramResult::Commutes {
+                checked_elements,
+                set_names,
+                ..
+            } => {
+                assert_eq!(set_names, vec!["A".to_owned(), "B".to_owned()]);
+                assert_eq!(checked_elements, vec![0, 3]);
+            }
+            CommutativeDiagramResult::DoesNotCommute(counterexamples) => {
+                panic!("{}", counterexamples[0])
+            }
+            CommutativeDiagramResult::Incomplete { stopped, .. } => {
+                panic!("Stopped early: {:?}", stopped)
+            }
+        }
+    }
+
+    #[test]
+    fn test_typed_objects() {
+        let mut builder = DiagramBuilder::new();
+        let naturals = builder.object("N", Set::new((0..10).collect::<Vec<u32>>()));
+        let integers = builder.object("Z", Set::<i32, _, _>::new_no_generating_set());
+        let strings = builder.object("S", Set::<String, _, _>::new_no_generating_set());
+
+        builder
+            .arrow(&naturals, &integers, |x: &u32| *x as i32, "as_i32")
+            .arrow(&naturals, &strings, |x: &u32| x.to_string(), "to_string")
+            .arrow(&integers, &strings, |x: &i32| x.to_string(), "to_string");
+        let diagram = builder.build().unwrap();
+
+        assert_eq!(diagram.set_name(2), "S");
+        match diagram_commutes(&diagram).unwrap() {
+            CommutativeDiagramResult::Commutes { .. } => {}
+            CommutativeDiagramResult::DoesNotCommute(counterexamples) => {
+                panic!("{}", counterexamples[0])
+            }
+            CommutativeDiagramResult::Incomplete { stopped, .. } => {
+                panic!("Stopped early: {:?}", stopped)
+            }
+        }
+    }
+
+    #[test]
+    fn test_named_and_typed_sets_combine() {
+        let mut builder = DiagramBuilder::new();
+        let naturals = builder.object("N", Set::new((0..10).collect::<Vec<u32>>()));
+        let integers = builder.object("Z", Set::<i32, _, _>::new_no_generating_set());
+        builder
+            .set("S", Set::<String, _, _>::new_no_generating_set())
+            .arrow(&naturals, &integers, |x: &u32| *x as i32, "as_i32")
+            .map("Z", "S", |x: &i32| x.to_string(), "to_string")
+            .map("N", "S", |x: &u32| x.to_string(), "to_string");
+        let diagram = builder.build().unwrap();
+
+        assert!(matches!(
+            diagram_commutes(&diagram).unwrap(),
+            CommutativeDiagramResult::Commutes { .. }
+        ));
+    }
+
+    #[test]
+    fn test_invalid_names_are_reported() {
+        let unknown = DiagramBuilder::new()
+            .set("A", Set::new(vec![1]))
+            .map("A", "B", |x: &i32| *x, "id")
+            .build();
+        assert_eq!(
+            unknown.err(),
+            Some(DiagramError::UnknownSet {
+                map: "id".to_owned(),
+                set: "B".to_owned()
+            })
+        );
+
+        let duplicate = DiagramBuilder::new()
+            .set("A", Set::new(vec![1]))
+            .set("A", Set::new(vec![2]))
+            .build();
+        assert_eq!(
+            duplicate.err(),
+            Some(DiagramError::DuplicateSet("A".to_owned()))
+        );
+    }
+}
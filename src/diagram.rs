@@ -40,16 +40,25 @@
 //!
 //! assert!(match diagram_commutes(&diagram).unwrap() {
 //!     CommutativeDiagramResult::Commutes{ .. } => true,
-//!     CommutativeDiagramResult::DoesNotCommute(reason) => panic!("{}", reason),
+//!     CommutativeDiagramResult::DoesNotCommute(counterexamples) => panic!("{}", counterexamples[0]),
+//!     CommutativeDiagramResult::Incomplete { stopped, .. } => panic!("Stopped early: {:?}", stopped),
 //! });
 //!```
 //!
 
 pub use crate::graph::CyclicGraphError;
-use crate::graph::{all_paths, DiGraph, Edge};
+use crate::graph::{DiGraph, Edge};
 use dyn_clonable::*;
-use std::any::Any;
+use std::any::{Any, TypeId};
+#[cfg(feature = "quickcheck")]
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+#[cfg(feature = "quickcheck")]
+use std::hash::{BuildHasher, Hasher};
+use std::marker::PhantomData;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
 #[clonable]
 pub trait Element: Clone {
@@ -66,10 +75,73 @@ pub trait SetLike {
 
     // If false, the set element is filtered from validation
     fn filter(&self, element: &Rc<dyn Element>) -> bool;
+
+    // Proposes simpler candidates for a failing element, used to shrink counterexamples.
+    // Candidates are tried in order, and the first one that still fails is shrunk further.
+    fn shrink(&self, _element: &Rc<dyn Element>) -> Box<dyn Iterator<Item = Rc<dyn Element>>> {
+        Box::new(std::iter::empty())
+    }
+
+    // The seed the generating elements were drawn with, if they were generated randomly. Reported in the check results
+    fn seed(&self) -> Option<u64> {
+        None
+    }
+
+    // The type of the elements in this set, if known, so that maps into and out of it can be validated
+    fn element_type(&self) -> Option<ElementType> {
+        None
+    }
+
+    // Whether `a` and `b` count as the same element when comparing the ends of two paths. Defaults to equality
+    fn equivalent(&self, a: &Rc<dyn Element>, b: &Rc<dyn Element>) -> bool {
+        a.eq(b)
+    }
+
+    // The normal form of an element of this set, if it is a quotient. Paths ending in this set are compared (and reported) on the normal forms
+    fn normalise(&self, element: &Rc<dyn Element>) -> Rc<dyn Element> {
+        element.clone()
+    }
+
+    // Whether `a` is below `b` in the order on this set, used to compare the paths of lax faces. Defaults to equivalence
+    fn le(&self, a: &Rc<dyn Element>, b: &Rc<dyn Element>) -> bool {
+        self.equivalent(a, b)
+    }
+}
+
+/// The type of an element, used to validate that maps fit the sets they connect
+#[derive(Clone, Copy, Debug)]
+pub struct ElementType {
+    id: TypeId,
+    name: &'static str,
+}
+
+impl ElementType {
+    pub fn of<T: 'static>() -> ElementType {
+        ElementType {
+            id: TypeId::of::<T>(),
+            name: std::any::type_name::<T>(),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
 }
 
+impl PartialEq for ElementType {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+// The types of the optional closures of a set that doesn't configure them, see the `with_*` methods on `Set`.
+// Like the property and filter, the closures are generic so that a set is `Send + Sync` (see `crate::sync`) exactly when they are
+type Shrinker<T> = fn(&T) -> Box<dyn Iterator<Item = T>>;
+type Relation<T> = fn(&T, &T) -> bool;
+type Normaliser<T> = fn(&T) -> Rc<dyn Element>;
+
 #[derive(Clone)]
-pub struct Set<T, P, F>
+pub struct Set<T, P, F, S = Shrinker<T>, O = Relation<T>, E = Relation<T>, N = Normaliser<T>>
 where
     T: Clone + Element + Sized,
     P: Fn(&T) -> bool,
@@ -78,25 +150,68 @@ where
     elements: Vec<T>,
     property: P,
     filter: F,
+    shrink: Option<S>,
+    seed: Option<u64>,
+    order: Option<O>,
+    equivalence: Option<E>,
+    normaliser: Option<N>,
 }
 
 impl<T> Set<T, fn(&T) -> bool, fn(&T) -> bool>
 where
     T: Clone + Element + Sized,
 {
-    pub fn new(elements: Vec<T>) -> Rc<Set<T, fn(&T) -> bool, fn(&T) -> bool>> {
-        Rc::new(Self {
-            elements,
-            property: |_x| true,
-            filter: |_x| true,
-        })
+    pub fn new(elements: Vec<T>) -> Rc<Self> {
+        Rc::new(Self::from_parts(elements, |_x| true, |_x| true))
+    }
+
+    pub fn new_no_generating_set() -> Rc<Self> {
+        Rc::new(Self::from_parts(Vec::new(), |_x| true, |_x| true))
+    }
+
+    // Builds a set ordered by its `PartialOrd` implementation, see `Set::with_order`
+    pub fn new_partially_ordered(elements: Vec<T>) -> Rc<Self>
+    where
+        T: PartialOrd,
+    {
+        Self::new(elements).with_order(T::le)
+    }
+
+    pub fn new_no_generating_set_partially_ordered() -> Rc<Self>
+    where
+        T: PartialOrd,
+    {
+        Self::new_partially_ordered(Vec::new())
+    }
+}
+
+/// The size that arbitrary elements are generated with, see `quickcheck::Gen::size`.
+/// It bounds e.g. the magnitude of arbitrary integers and the length of arbitrary vectors.
+#[cfg(feature = "quickcheck")]
+pub const ARBITRARY_SIZE: usize = 100;
+
+#[cfg(feature = "quickcheck")]
+impl<T> Set<T, fn(&T) -> bool, fn(&T) -> bool>
+where
+    T: Clone + Element + Sized + quickcheck::Arbitrary,
+{
+    // Samples `count` arbitrary generating elements with a random seed, which is reported in the check results.
+    // The elements are generated with size `ARBITRARY_SIZE`, and counterexamples are shrunk using `Arbitrary::shrink`.
+    pub fn new_arbitrary(count: usize) -> Rc<Self> {
+        // Arbitrary u64s are biased towards a few special values, so the seed is drawn from the randomly keyed std hasher instead
+        let seed = RandomState::new().build_hasher().finish();
+        Self::new_arbitrary_seeded(count, seed)
     }
 
-    pub fn new_no_generating_set() -> Rc<Set<T, fn(&T) -> bool, fn(&T) -> bool>> {
+    // Samples `count` arbitrary generating elements from the given seed, e.g. to replay a failing run
+    pub fn new_arbitrary_seeded(count: usize, seed: u64) -> Rc<Self> {
+        let mut gen = quickcheck::Gen::from_size_and_seed(ARBITRARY_SIZE, seed);
+        let elements = (0..count).map(|_| T::arbitrary(&mut gen)).collect();
+
         Rc::new(Self {
-            elements: Vec::new(),
-            property: |_x| true,
-            filter: |_x| true,
+            shrink: Some(|x: &T| x.shrink()),
+            seed: Some(seed),
+            ..Self::from_parts(elements, |_x| true, |_x| true)
         })
     }
 }
@@ -106,20 +221,12 @@ where
     T: Clone + Element + Sized,
     P: Fn(&T) -> bool,
 {
-    pub fn new_checked(elements: Vec<T>, property: P) -> Rc<Set<T, P, fn(&T) -> bool>> {
-        Rc::new(Self {
-            elements,
-            property,
-            filter: |_x| true,
-        })
+    pub fn new_checked(elements: Vec<T>, property: P) -> Rc<Self> {
+        Rc::new(Self::from_parts(elements, property, |_x| true))
     }
 
-    pub fn new_no_generating_set_checked(property: P) -> Rc<Set<T, P, fn(&T) -> bool>> {
-        Rc::new(Self {
-            elements: Vec::new(),
-            property,
-            filter: |_x| true,
-        })
+    pub fn new_no_generating_set_checked(property: P) -> Rc<Self> {
+        Rc::new(Self::from_parts(Vec::new(), property, |_x| true))
     }
 }
 
@@ -128,20 +235,12 @@ where
     T: Clone + Element + Sized,
     F: Fn(&T) -> bool,
 {
-    pub fn new_filtered(elements: Vec<T>, filter: F) -> Rc<Set<T, fn(&T) -> bool, F>> {
-        Rc::new(Self {
-            elements,
-            property: |_x| true,
-            filter,
-        })
+    pub fn new_filtered(elements: Vec<T>, filter: F) -> Rc<Self> {
+        Rc::new(Self::from_parts(elements, |_x| true, filter))
     }
 
-    pub fn new_no_generating_set_filtered(filter: F) -> Rc<Set<T, fn(&T) -> bool, F>> {
-        Rc::new(Self {
-            elements: Vec::new(),
-            property: |_x| true,
-            filter,
-        })
+    pub fn new_no_generating_set_filtered(filter: F) -> Rc<Self> {
+        Rc::new(Self::from_parts(Vec::new(), |_x| true, filter))
     }
 }
 
@@ -152,27 +251,139 @@ where
     F: Fn(&T) -> bool,
 {
     pub fn new_checked_filtered(elements: Vec<T>, property: P, filter: F) -> Rc<Set<T, P, F>> {
-        Rc::new(Self {
+        Rc::new(Self::from_parts(elements, property, filter))
+    }
+
+    pub fn new_no_generating_set_checked_filtered(property: P, filter: F) -> Rc<Set<T, P, F>> {
+        Rc::new(Self::from_parts(Vec::new(), property, filter))
+    }
+
+    fn from_parts(elements: Vec<T>, property: P, filter: F) -> Self {
+        Self {
             elements,
             property,
             filter,
+            shrink: None,
+            seed: None,
+            order: None,
+            equivalence: None,
+            normaliser: None,
+        }
+    }
+}
+
+impl<T, P, F, S, O, E, N> Set<T, P, F, S, O, E, N>
+where
+    T: Clone + Element + Sized,
+    P: Fn(&T) -> bool,
+    F: Fn(&T) -> bool,
+{
+    // The methods below configure a set right after it was built, e.g. `Set::new(elements).with_order(le)`,
+    // and can be combined with each other. The set is only cloned if it is already shared elsewhere.
+    // None of the closures need to be `Send + Sync`, unless the set is shared between threads with `crate::sync::set`
+
+    // Shrinks failing elements by proposing simpler candidates, see `SetLike::shrink`
+    #[allow(clippy::type_complexity)] // The shrinker can't be named, so it can't be factored out
+    pub fn with_shrinker<S2, I>(
+        self: Rc<Self>,
+        shrink: S2,
+    ) -> Rc<Set<T, P, F, impl Fn(&T) -> Box<dyn Iterator<Item = T>> + Clone, O, E, N>>
+    where
+        Self: Clone,
+        S2: Fn(&T) -> I + Clone + 'static,
+        I: IntoIterator<Item = T>,
+        I::IntoIter: 'static,
+    {
+        let set = Rc::unwrap_or_clone(self);
+        Rc::new(Set {
+            elements: set.elements,
+            property: set.property,
+            filter: set.filter,
+            shrink: Some(move |x: &T| {
+                Box::new(shrink(x).into_iter()) as Box<dyn Iterator<Item = T>>
+            }),
+            seed: set.seed,
+            order: set.order,
+            equivalence: set.equivalence,
+            normaliser: set.normaliser,
         })
     }
 
-    pub fn new_no_generating_set_checked_filtered(property: P, filter: F) -> Rc<Set<T, P, F>> {
-        Rc::new(Self {
-            elements: Vec::new(),
-            property,
-            filter,
+    // Orders the set by `le`, so that lax faces ending in it only need their lower path to stay below the upper one
+    pub fn with_order<O2>(self: Rc<Self>, le: O2) -> Rc<Set<T, P, F, S, O2, E, N>>
+    where
+        Self: Clone,
+        O2: Fn(&T, &T) -> bool,
+    {
+        let set = Rc::unwrap_or_clone(self);
+        Rc::new(Set {
+            elements: set.elements,
+            property: set.property,
+            filter: set.filter,
+            shrink: set.shrink,
+            seed: set.seed,
+            order: Some(le),
+            equivalence: set.equivalence,
+            normaliser: set.normaliser,
+        })
+    }
+
+    // Compares elements with `equivalent` at the end of paths, rather than with `==`.
+    // This allows e.g. approximate equality of floats, which rarely agree exactly after rounding
+    pub fn with_equivalence<E2>(self: Rc<Self>, equivalent: E2) -> Rc<Set<T, P, F, S, O, E2, N>>
+    where
+        Self: Clone,
+        E2: Fn(&T, &T) -> bool,
+    {
+        let set = Rc::unwrap_or_clone(self);
+        Rc::new(Set {
+            elements: set.elements,
+            property: set.property,
+            filter: set.filter,
+            shrink: set.shrink,
+            seed: set.seed,
+            order: set.order,
+            equivalence: Some(equivalent),
+            normaliser: set.normaliser,
+        })
+    }
+
+    // Makes this a quotient set, whose elements are identified by their normal form (e.g. reduced fractions, or sorted
+    // vectors for multisets). Paths ending in this set are compared on the normal forms, which are also what gets reported.
+    // Normal forms are compared for equality, so they take the place of any equivalence or order on the set
+    #[allow(clippy::type_complexity)] // The normaliser can't be named, so it can't be factored out
+    pub fn with_normal_form<N2, K>(
+        self: Rc<Self>,
+        normalise: N2,
+    ) -> Rc<Set<T, P, F, S, O, E, impl Fn(&T) -> Rc<dyn Element> + Clone>>
+    where
+        Self: Clone,
+        N2: Fn(&T) -> K + Clone + 'static,
+        K: Clone + Eq + std::hash::Hash + core::fmt::Debug + 'static,
+    {
+        let set = Rc::unwrap_or_clone(self);
+        Rc::new(Set {
+            elements: set.elements,
+            property: set.property,
+            filter: set.filter,
+            shrink: set.shrink,
+            seed: set.seed,
+            order: set.order,
+            equivalence: set.equivalence,
+            normaliser: Some(move |x: &T| Rc::new(normalise(x)) as Rc<dyn Element>),
         })
     }
 }
 
-impl<T, P, F> SetLike for Set<T, P, F>
+impl<T, P, F, S, O, E, N> SetLike for Set<T, P, F, S, O, E, N>
 where
     T: Clone + Element + Sized + 'static,
     P: Fn(&T) -> bool,
     F: Fn(&T) -> bool,
+    S: Fn(&T) -> Box<dyn Iterator<Item = T>>,
+    O: Fn(&T, &T) -> bool,
+    E: Fn(&T, &T) -> bool,
+    N: Fn(&T) -> Rc<dyn Element>,
 {
     fn elements(&self) -> Box<dyn Iterator<Item = Rc<dyn Element>>> {
         let owned_els = self.elements.clone();
@@ -190,10 +401,58 @@ where
     fn filter(&self, element: &Rc<dyn Element>) -> bool {
         (self.filter)(element.as_any().downcast_ref::<T>().unwrap())
     }
+
+    fn shrink(&self, element: &Rc<dyn Element>) -> Box<dyn Iterator<Item = Rc<dyn Element>>> {
+        match &self.shrink {
+            Some(shrink) => Box::new(
+                shrink(element.as_any().downcast_ref::<T>().unwrap())
+                    .map(|e| Rc::new(e) as Rc<dyn Element>),
+            ),
+            None => Box::new(std::iter::empty()),
+        }
+    }
+
+    fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    fn element_type(&self) -> Option<ElementType> {
+        Some(ElementType::of::<T>())
+    }
+
+    fn le(&self, a: &Rc<dyn Element>, b: &Rc<dyn Element>) -> bool {
+        match &self.order {
+            // Normal forms need not be elements of this set, see `Set::with_normal_form`
+            Some(le) if self.normaliser.is_none() => le(
+                a.as_any().downcast_ref::<T>().unwrap(),
+                b.as_any().downcast_ref::<T>().unwrap(),
+            ),
+            _ => self.equivalent(a, b),
+        }
+    }
+
+    fn normalise(&self, element: &Rc<dyn Element>) -> Rc<dyn Element> {
+        match &self.normaliser {
+            Some(normalise) => normalise(element.as_any().downcast_ref::<T>().unwrap()),
+            None => element.clone(),
+        }
+    }
+
+    fn equivalent(&self, a: &Rc<dyn Element>, b: &Rc<dyn Element>) -> bool {
+        match &self.equivalence {
+            Some(equivalent) if self.normaliser.is_none() => equivalent(
+                a.as_any().downcast_ref::<T>().unwrap(),
+                b.as_any().downcast_ref::<T>().unwrap(),
+            ),
+            _ => a.eq(b),
+        }
+    }
 }
 
-trait Mappable {
-    fn map(&self, key: &Rc<dyn Element>) -> Option<Rc<dyn Element>>;
+pub(crate) trait Mappable {
+    fn map(&self, key: &Rc<dyn Element>) -> Result<Rc<dyn Element>, Unmapped>;
+    fn domain(&self) -> ElementType;
+    fn codomain(&self) -> ElementType;
 }
 
 #[derive(Clone, Copy, PartialEq, Debug)]
@@ -252,7 +511,7 @@ impl DiGraph for Diagram {
                 )| DiEdge {
                     from: *from,
                     to: *to,
-                    ix: ix,
+                    ix,
                 },
             )
             .collect();
@@ -261,314 +520,2863 @@ impl DiGraph for Diagram {
     }
 }
 
-struct ValueMap<T, U> {
-    map: Rc<dyn Fn(&T) -> U>,
+pub(crate) struct ValueMap<T, U, F> {
+    map: F,
+    types: PhantomData<fn(&T) -> U>,
+}
+
+impl<T, U, F> ValueMap<T, U, F> {
+    pub(crate) fn new(map: F) -> ValueMap<T, U, F> {
+        ValueMap {
+            map,
+            types: PhantomData,
+        }
+    }
 }
 
-impl<T, U> Mappable for ValueMap<T, U>
+impl<T, U, F> Mappable for ValueMap<T, U, F>
 where
     T: Element + 'static,
     U: Element + 'static,
+    F: Fn(&T) -> U,
 {
-    fn map(&self, key: &Rc<dyn Element>) -> Option<Rc<dyn Element>> {
+    fn map(&self, key: &Rc<dyn Element>) -> Result<Rc<dyn Element>, Unmapped> {
         if let Some(key) = key.as_any().downcast_ref::<T>() {
-            Some(Rc::new((self.map)(key)))
+            Ok(Rc::new((self.map)(key)))
         } else {
-            None
+            Err(Unmapped::WrongType)
         }
     }
-}
 
-impl<T, U> ValueMap<T, U>
-where
-    T: Element + 'static,
-    U: Element + 'static,
-{
-    pub fn new<M>(map: M) -> Rc<ValueMap<T, U>>
-    where
-        M: Fn(&T) -> U + 'static,
-    {
-        Rc::new(ValueMap {
-            map: Rc::new(move |x| map(x)),
-        })
+    fn domain(&self) -> ElementType {
+        ElementType::of::<T>()
+    }
+
+    fn codomain(&self) -> ElementType {
+        ElementType::of::<U>()
     }
 }
 
-pub struct Map {
-    from: usize,
-    to: usize,
-    map: Rc<dyn Mappable>,
-    name: String,
+type Fallible<T, U, E> = fn(&T) -> Result<U, E>;
+
+pub(crate) struct FallibleMap<T, U, E, F> {
+    map: F,
+    on_error: OnMapError,
+    types: PhantomData<Fallible<T, U, E>>,
 }
 
-impl Map {
-    pub fn new<F, U, V>(from: usize, to: usize, map: F, name: &str) -> Map
-    where
-        F: Fn(&U) -> V + 'static + Clone,
-        U: Clone + PartialEq + core::fmt::Debug + 'static,
-        V: Clone + PartialEq + core::fmt::Debug + 'static,
-    {
-        Map {
-            from: from,
-            to: to,
-            map: ValueMap::new(map.clone()),
-            name: name.to_owned(),
+impl<T, U, E, F> FallibleMap<T, U, E, F> {
+    pub(crate) fn new(map: F, on_error: OnMapError) -> FallibleMap<T, U, E, F> {
+        FallibleMap {
+            map,
+            on_error,
+            types: PhantomData,
         }
     }
 }
 
-pub struct Diagram {
-    sets: Vec<Rc<dyn SetLike>>,
-    maps: Vec<Map>, // (usize, usize, Rc<dyn Mappable>, String)>,
-}
+impl<T, U, E, F> Mappable for FallibleMap<T, U, E, F>
+where
+    T: Element + 'static,
+    U: Element + 'static,
+    E: core::fmt::Debug + 'static,
+    F: Fn(&T) -> Result<U, E>,
+{
+    fn map(&self, key: &Rc<dyn Element>) -> Result<Rc<dyn Element>, Unmapped> {
+        let Some(key) = key.as_any().downcast_ref::<T>() else {
+            return Err(Unmapped::WrongType);
+        };
 
-#[derive(Clone, Debug)]
-pub enum CommutativeDiagramResult {
-    Commutes {
-        checked_elements: Vec<usize>, // Set ix -> How many elements we encountered (not generated there) in this set. Includes intermediates and finals
-    },
-    DoesNotCommute(String),
-}
+        match (self.map)(key) {
+            Ok(image) => Ok(Rc::new(image)),
+            Err(_error) if self.on_error == OnMapError::Exclude => Err(Unmapped::Excluded),
+            Err(error) => Err(Unmapped::Failed(format!("{:?}", error))),
+        }
+    }
 
-impl Diagram {
-    pub fn new(sets: Vec<Rc<dyn SetLike>>, maps: Vec<Map>) -> Diagram {
-        Diagram { sets, maps }
+    fn domain(&self) -> ElementType {
+        ElementType::of::<T>()
     }
-}
 
-#[derive(Clone, Debug)]
-pub enum CommutativeDiagramError {
-    CyclicGraphError,
-    PropertyCheckError(String),
+    fn codomain(&self) -> ElementType {
+        ElementType::of::<U>()
+    }
 }
 
-pub fn diagram_commutes(
-    diagram: &Diagram,
-) -> Result<CommutativeDiagramResult, CommutativeDiagramError> {
-    // Find all paths through the diagram
-    let all_possible_paths =
-        all_paths(diagram).map_err(|_err| CommutativeDiagramError::CyclicGraphError)?;
+pub(crate) struct PartialMap<T, U, F, D> {
+    map: F,
+    domain: D,
+    types: PhantomData<fn(&T) -> U>,
+}
 
-    let mut checked_elements = vec![0; diagram.sets.len()];
+impl<T, U, F, D> PartialMap<T, U, F, D> {
+    pub(crate) fn new(map: F, domain: D) -> PartialMap<T, U, F, D> {
+        PartialMap {
+            map,
+            domain,
+            types: PhantomData,
+        }
+    }
+}
 
-    // For each pair of paths...
-    for (path_a_ix, path_a) in all_possible_paths.clone().iter().enumerate() {
-        for (path_b_ix, path_b) in all_possible_paths.clone().iter().enumerate() {
-            // No need to check pairs of paths both ways
-            if path_b_ix < path_a_ix {
-                continue;
-            }
+impl<T, U, F, D> Mappable for PartialMap<T, U, F, D>
+where
+    T: Element + 'static,
+    U: Element + 'static,
+    F: Fn(&T) -> U,
+    D: Fn(&T) -> bool,
+{
+    fn map(&self, key: &Rc<dyn Element>) -> Result<Rc<dyn Element>, Unmapped> {
+        let Some(key) = key.as_any().downcast_ref::<T>() else {
+            return Err(Unmapped::WrongType);
+        };
 
-            // Check if these two paths match
-            if path_a.first().map(|e| e.from()) == path_b.first().map(|e| e.from())
-                && path_a.last().map(|e| e.to()) == path_b.last().map(|e| e.to())
-            {
-                // The paths line up, let's look at every elemnt of their common source
-                let common_source: &<Diagram as DiGraph>::Node =
-                    path_a.first().map(|e| e.from()).unwrap();
-
-                // Find each element of the common source
-                let source_set = &diagram.sets[*common_source];
-
-                // Now, map this source set through both of the paths
-                let source_elements = source_set.elements();
-                'outer: for element in source_elements {
-                    // Check if this element should be filtered
-                    if !source_set.filter(&element) {
-                        continue 'outer; // Next!
-                    }
+        // The map itself is never called outside its domain
+        if !(self.domain)(key) {
+            return Err(Unmapped::Excluded);
+        }
+        Ok(Rc::new((self.map)(key)))
+    }
 
-                    // Verify the element in the source set.
-                    if !source_set.check(&element) {
-                        return Err(CommutativeDiagramError::PropertyCheckError(format!(
-                            "Element does not satisfy source set property: {:?}",
-                            element.name().clone()
-                        )));
-                    }
+    fn domain(&self) -> ElementType {
+        ElementType::of::<T>()
+    }
 
-                    let mut path_a_element = element.clone();
-                    let mut a_names: Vec<String> = Vec::new();
-                    let mut b_names: Vec<String> = Vec::new();
-
-                    for edge in path_a {
-                        let map = &diagram.maps[edge.ix].map;
-                        let set = &diagram.sets[*edge.to()];
-
-                        // Record that we found an element
-                        checked_elements[*edge.to()] += 1;
-
-                        path_a_element = map.map(&path_a_element).unwrap();
-                        a_names.push(path_a_element.name().clone());
-
-                        // Check if this element/path should be filtered
-                        if !set.filter(&path_a_element) {
-                            continue 'outer;
-                        }
-
-                        // Check if this element passes validation
-                        if !set.check(&path_a_element) {
-                            let path_description = path_a
-                                .iter()
-                                .map(|edge| diagram.maps[edge.ix].name.clone())
-                                .collect::<Vec<String>>()
-                                .join(" -> ");
-                            let element_names = a_names.join(" -> ");
-
-                            return Err(CommutativeDiagramError::PropertyCheckError(format!(
-                                "Element does not satisfy target set property: {:?} along path {} with elements in between seen {}, starting from {}",
-                                path_a_element.name().clone(),
-                                path_description,
-                                element_names,
-                                element.name().clone()
-                            )));
-                        }
-                    }
+    fn codomain(&self) -> ElementType {
+        ElementType::of::<U>()
+    }
+}
 
-                    let mut path_b_element = element.clone();
-
-                    for edge in path_b {
-                        let map = &diagram.maps[edge.ix].map;
-                        let set = &diagram.sets[*edge.to()];
-
-                        // Record that we found an element
-                        checked_elements[*edge.to()] += 1;
-
-                        path_b_element = map.map(&path_b_element).unwrap();
-                        b_names.push(path_b_element.name().clone());
-
-                        // Check if this element/path should be filtered
-                        if !set.filter(&path_b_element) {
-                            continue 'outer;
-                        }
-
-                        // Check if this element passes validation
-                        if !set.check(&path_b_element) {
-                            let path_description = path_b
-                                .iter()
-                                .map(|edge| diagram.maps[edge.ix].name.clone())
-                                .collect::<Vec<String>>()
-                                .join(" -> ");
-                            let element_names = b_names.join(" -> ");
-
-                            return Err(CommutativeDiagramError::PropertyCheckError(format!(
-                                "Element does not satisfy target set property: {:?} along path {} with elements in between seen {}, starting from {}",
-                                path_b_element.name().clone(),
-                                path_description,
-                                element_names,
-                                element.name().clone()
-                            )));
-                        }
-                    }
+pub(crate) struct RelationMap<T, U, F> {
+    map: F,
+    types: PhantomData<fn(&T) -> Vec<U>>,
+}
 
-                    // Now, check if the two elements are equal
-                    if !path_a_element.eq(&path_b_element) {
-                        // Get descriptions for both paths
-                        let path_a_description = path_a
-                            .iter()
-                            .map(|edge| diagram.maps[edge.ix].name.clone())
-                            .collect::<Vec<String>>()
-                            .join(" -> ");
-
-                        let path_b_description = path_b
-                            .iter()
-                            .map(|edge| diagram.maps[edge.ix].name.clone())
-                            .collect::<Vec<String>>()
-                            .join(" -> ");
-
-                        let element_name = element.name().clone();
-                        let left_final_element_name = path_a_element.name();
-                        let right_final_element_name = path_b_element.name();
-
-                        let left_element_names = a_names.join(" -> ");
-                        let right_element_names = b_names.join(" -> ");
-
-                        let reason = format!(
-                            "{} and {} don't agree on {}. Left gets {} while right gets {}. Intermediates left are {} and right are {}",
-                            path_a_description,
-                            path_b_description,
-                            element_name,
-                            left_final_element_name,
-                            right_final_element_name,
-                            left_element_names,
-                            right_element_names
-                        );
-
-                        return Ok(CommutativeDiagramResult::DoesNotCommute(reason.to_owned()));
-                    }
-                }
-            }
+impl<T, U, F> RelationMap<T, U, F> {
+    pub(crate) fn new(map: F) -> RelationMap<T, U, F> {
+        RelationMap {
+            map,
+            types: PhantomData,
         }
     }
-
-    Ok(CommutativeDiagramResult::Commutes { checked_elements })
 }
 
-impl<T> Element for T
+impl<T, U, F> Mappable for RelationMap<T, U, F>
 where
-    T: Clone + PartialEq + Sized + 'static + core::fmt::Debug,
+    T: Element + 'static,
+    U: Element + 'static,
+    F: Fn(&T) -> Vec<U>,
 {
-    fn as_any(&self) -> &dyn Any {
-        self
-    }
-
-    fn eq(&self, other: &Rc<dyn Element>) -> bool {
-        if let Some(other) = other.as_any().downcast_ref::<T>() {
-            self == other
+    fn map(&self, key: &Rc<dyn Element>) -> Result<Rc<dyn Element>, Unmapped> {
+        if let Some(key) = key.as_any().downcast_ref::<T>() {
+            Ok(Rc::new(Outcomes::new(
+                (self.map)(key)
+                    .into_iter()
+                    .map(|outcome| Rc::new(outcome) as Rc<dyn Element>),
+            )))
         } else {
-            false
+            Err(Unmapped::WrongType)
         }
     }
 
-    fn name(&self) -> String {
-        format!("{:#?}", self)
+    fn domain(&self) -> ElementType {
+        ElementType::of::<T>()
+    }
+
+    fn codomain(&self) -> ElementType {
+        ElementType::of::<U>()
     }
 }
 
-#[cfg(test)]
-mod tests {
+/// The outcomes of mapping an element through relations, see `Map::new_relation`.
+/// Outcomes are compared as sets, so their order and any duplicates don't matter
+#[derive(Clone)]
+pub struct Outcomes(Vec<Rc<dyn Element>>);
 
-    use super::*;
+impl Outcomes {
+    fn new<I>(outcomes: I) -> Outcomes
+    where
+        I: IntoIterator<Item = Rc<dyn Element>>,
+    {
+        let mut distinct = Outcomes(Vec::new());
+        for outcome in outcomes {
+            if !distinct.contains(&outcome) {
+                distinct.0.push(outcome);
+            }
+        }
+        distinct
+    }
 
-    #[test]
-    fn test_addition_is_associative_on_integers() {
-        use itertools::Itertools;
+    // The outcomes of an element at the end of a path, which is a single one unless the path passes through a relation
+    fn of(element: &Rc<dyn Element>) -> Outcomes {
+        match element.as_any().downcast_ref::<Outcomes>() {
+            Some(outcomes) => outcomes.clone(),
+            None => Outcomes(vec![element.clone()]),
+        }
+    }
 
-        let generating_integers: Vec<i32> = (0..20).collect();
+    pub fn elements(&self) -> &[Rc<dyn Element>] {
+        &self.0
+    }
 
-        let left_add = |(a, b, c): &(i32, i32, i32)| (a + b, *c);
-        let right_add = |(a, b, c): &(i32, i32, i32)| (*a, b + c);
+    fn contains(&self, element: &Rc<dyn Element>) -> bool {
+        self.0.iter().any(|outcome| outcome.eq(element))
+    }
 
-        // Build the sets (triplets, pairs, integers) so that summing operations can be well-defined later
-        let triplets: Vec<(i32, i32, i32)> = (generating_integers)
-            .clone()
+    fn is_subset(&self, other: &Outcomes) -> bool {
+        self.is_covered_by(other, |a, b| a.eq(b))
+    }
+
+    // Whether every outcome is related to some outcome of `other`
+    fn is_covered_by<R>(&self, other: &Outcomes, related: R) -> bool
+    where
+        R: Fn(&Rc<dyn Element>, &Rc<dyn Element>) -> bool,
+    {
+        self.0
             .iter()
-            .cartesian_product(generating_integers.clone())
-            .cartesian_product(generating_integers.clone().iter())
-            .map(|((a, b), c)| (*a, b, *c))
-            .collect();
+            .all(|outcome| other.0.iter().any(|o| related(outcome, o)))
+    }
+}
 
-        let diagram = Diagram {
-            sets: vec![
-                Set::new(triplets),
-                Set::<(i32, i32), _, _>::new_no_generating_set_filtered(|(a, b): &(i32, i32)| {
-                    a + b >= 6
-                }),
-                Set::<(i32, i32), _, _>::new_no_generating_set_filtered(|(a, b): &(i32, i32)| {
-                    a + b >= 5
-                }),
-                Set::<i32, _, _>::new_no_generating_set_checked(|x: &i32| *x >= 5),
-            ],
-            maps: vec![
-                Map::new(0, 1, left_add, "(+,id)"),
-                Map::new(0, 2, right_add, "(id,+)"),
-                Map::new(2, 3, |(a, b): &(i32, i32)| a + b, "(+)"),
-                Map::new(1, 3, |(a, b): &(i32, i32)| a + b, "(+)"),
-            ],
-        };
+impl PartialEq for Outcomes {
+    fn eq(&self, other: &Self) -> bool {
+        self.is_subset(other) && other.is_subset(self)
+    }
+}
 
-        assert!(match diagram_commutes(&diagram).unwrap() {
-            CommutativeDiagramResult::Commutes { .. } => true,
-            CommutativeDiagramResult::DoesNotCommute(reason) => panic!("{}", reason),
+impl std::fmt::Debug for Outcomes {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let names: Vec<String> = self.0.iter().map(|outcome| outcome.name()).collect();
+        write!(f, "{{{}}}", names.join(", "))
+    }
+}
+
+// Why a map did not give an image for an element
+pub(crate) enum Unmapped {
+    WrongType,      // The element is not of the type the map takes
+    Excluded, // The map is not defined on the element (or failed on it), so it is left out of the check
+    Failed(String), // The map failed on the element, with the Debug output of its error
+}
+
+/// What a fallible map does with the elements it fails on
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OnMapError {
+    Exclude, // Leave the element out of the check, as if it were filtered
+    Report,  // Stop the check with a `CommutativeDiagramError::MapError`
+}
+
+pub struct Map {
+    pub(crate) from: usize,
+    pub(crate) to: usize,
+    pub(crate) map: Rc<dyn Mappable>,
+    pub(crate) name: String,
+}
+
+impl Map {
+    pub fn new<F, U, V>(from: usize, to: usize, map: F, name: &str) -> Map
+    where
+        F: Fn(&U) -> V + 'static + Clone,
+        U: Clone + PartialEq + core::fmt::Debug + 'static,
+        V: Clone + PartialEq + core::fmt::Debug + 'static,
+    {
+        Map {
+            from,
+            to,
+            map: Rc::new(ValueMap::new(map)),
+            name: name.to_owned(),
+        }
+    }
+
+    // Builds a map that is only defined on the elements satisfying `domain`.
+    // Paths through it are only checked on elements where it is defined, without affecting the other maps into its target set
+    pub fn new_partial<F, D, U, V>(from: usize, to: usize, map: F, domain: D, name: &str) -> Map
+    where
+        F: Fn(&U) -> V + 'static + Clone,
+        D: Fn(&U) -> bool + 'static,
+        U: Clone + PartialEq + core::fmt::Debug + 'static,
+        V: Clone + PartialEq + core::fmt::Debug + 'static,
+    {
+        Map {
+            from,
+            to,
+            map: Rc::new(PartialMap::new(map, domain)),
+            name: name.to_owned(),
+        }
+    }
+
+    // Builds a relation, mapping each element to any number of outcomes (e.g. a nondeterministic transition).
+    // Paths through relations are composed relationally, and parallel paths must end up with the same outcomes
+    pub fn new_relation<F, U, V>(from: usize, to: usize, map: F, name: &str) -> Map
+    where
+        F: Fn(&U) -> Vec<V> + 'static + Clone,
+        U: Clone + PartialEq + core::fmt::Debug + 'static,
+        V: Clone + PartialEq + core::fmt::Debug + 'static,
+    {
+        Map {
+            from,
+            to,
+            map: Rc::new(RelationMap::new(map)),
+            name: name.to_owned(),
+        }
+    }
+
+    // Builds a map from a partial operation (parsing, division, checked arithmetic),
+    // where `on_error` decides whether an `Err` leaves the element out of the check or is reported
+    pub fn new_fallible<F, U, V, E>(
+        from: usize,
+        to: usize,
+        map: F,
+        name: &str,
+        on_error: OnMapError,
+    ) -> Map
+    where
+        F: Fn(&U) -> Result<V, E> + 'static + Clone,
+        U: Clone + PartialEq + core::fmt::Debug + 'static,
+        V: Clone + PartialEq + core::fmt::Debug + 'static,
+        E: core::fmt::Debug + 'static,
+    {
+        Map {
+            from,
+            to,
+            map: Rc::new(FallibleMap::new(map, on_error)),
+            name: name.to_owned(),
+        }
+    }
+}
+
+pub struct Diagram {
+    pub(crate) sets: Vec<Rc<dyn SetLike>>,
+    pub(crate) maps: Vec<Map>, // (usize, usize, Rc<dyn Mappable>, String)>,
+    set_names: Vec<String>,    // Used to describe the diagram, defaults to the set indices
+    pub(crate) set_positions: Vec<Option<(usize, usize)>>, // Layout hints (row, column) for rendering
+    pub(crate) faces: Vec<(Vec<DiEdge>, Vec<DiEdge>, Comparison)>, // Declared pairs of paths, see `Diagram::with_face` and `Diagram::with_lax_face`
+}
+
+#[derive(Clone, Debug)]
+pub enum CommutativeDiagramResult {
+    Commutes {
+        checked_elements: Vec<usize>, // Set ix -> How many elements we encountered (not generated there) in this set. Includes intermediates and finals
+        path_length_bound: Option<usize>, // The maximum path length up to which the diagram was checked, if it was bounded
+        seeds: Vec<Option<u64>>, // Set ix -> The seed its generating elements were drawn with, if they were random
+        set_names: Vec<String>,  // Set ix -> Name of the set, to label the above
+    },
+    DoesNotCommute(Vec<Counterexample>), // The counterexamples found, at most as many as requested in `CheckOptions`
+    // The check stopped early without finding a counterexample, so the diagram only commutes on the elements checked so far.
+    // The other fields are as for `Commutes`
+    Incomplete {
+        stopped: Stopped,
+        checked_elements: Vec<usize>,
+        path_length_bound: Option<usize>,
+        seeds: Vec<Option<u64>>,
+        set_names: Vec<String>,
+    },
+}
+
+/// The maps along a path, with the elements an element was mapped to along the way
+#[derive(Clone, Debug)]
+pub struct PathTrace {
+    pub maps: Vec<usize>,                    // Map indices along the path
+    pub map_names: Vec<String>,              // Names of those maps
+    pub intermediates: Vec<Rc<dyn Element>>, // The image after each map, so the last one is the final result
+}
+
+impl PathTrace {
+    fn new(diagram: &Diagram, path: &[DiEdge], intermediates: Vec<Rc<dyn Element>>) -> PathTrace {
+        PathTrace {
+            maps: path.iter().map(|edge| edge.ix).collect(),
+            map_names: path
+                .iter()
+                .map(|edge| diagram.maps[edge.ix].name.clone())
+                .collect(),
+            intermediates,
+        }
+    }
+
+    fn describe_maps(&self) -> String {
+        self.map_names.join(" -> ")
+    }
+
+    fn describe_intermediates(&self) -> String {
+        self.intermediates
+            .iter()
+            .map(|element| element.name())
+            .collect::<Vec<String>>()
+            .join(" -> ")
+    }
+}
+
+/// A source element on which two parallel paths disagree
+#[derive(Clone, Debug)]
+pub struct Counterexample {
+    pub element: Rc<dyn Element>,
+    pub element_ix: usize, // Index of the element that originally failed among the generating elements of the source set
+    pub source_set: String, // Name of the set the element comes from
+    pub target_set: String, // Name of the set both paths end in
+    pub left: PathTrace,   // The reference path that was compared against
+    pub right: PathTrace,  // The path that disagrees with it
+    pub left_result: Rc<dyn Element>,
+    pub right_result: Rc<dyn Element>,
+    pub comparison: Comparison, // How the results should have related
+    pub shrunk_from: Option<Rc<dyn Element>>, // The element that originally failed, if this counterexample was shrunk
+    pub seed: Option<u64>, // The seed the source set's generating elements were drawn with, to replay the run
+}
+
+impl Counterexample {
+    fn new(
+        diagram: &Diagram,
+        element_ix: usize,
+        element: &Rc<dyn Element>,
+        left: (&[DiEdge], PathImage),
+        right: (&[DiEdge], PathImage),
+        comparison: Comparison,
+    ) -> Counterexample {
+        Counterexample {
+            element: element.clone(),
+            element_ix,
+            comparison,
+            source_set: diagram.set_name(*left.0.first().unwrap().from()).to_owned(),
+            target_set: diagram.set_name(*left.0.last().unwrap().to()).to_owned(),
+            left_result: left.1.result().clone(),
+            right_result: right.1.result().clone(),
+            left: PathTrace::new(diagram, left.0, left.1.intermediates),
+            seed: diagram.sets[*left.0.first().unwrap().from()].seed(),
+            right: PathTrace::new(diagram, right.0, right.1.intermediates),
+            shrunk_from: None,
+        }
+    }
+}
+
+impl std::fmt::Display for Counterexample {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let relation = match self.comparison {
+            Comparison::Equal => "and",
+            Comparison::Below => "is not below",
+        };
+        let disagreement = match self.comparison {
+            Comparison::Equal => " don't agree",
+            Comparison::Below => "",
+        };
+        write!(
+            f,
+            "{} {} {} from {} to {}{} on {}. Left gets {} while right gets {}. Intermediates left are {} and right are {}",
+            self.left.describe_maps(),
+            relation,
+            self.right.describe_maps(),
+            self.source_set,
+            self.target_set,
+            disagreement,
+            self.element.name(),
+            self.left_result.name(),
+            self.right_result.name(),
+            self.left.describe_intermediates(),
+            self.right.describe_intermediates()
+        )
+    }
+}
+
+/// How the results of two parallel paths must relate
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Comparison {
+    Equal, // Both paths give the same result, or the same outcomes if they pass through relations
+    Below, // The left result is below the right one in the order of their target set, see `Diagram::with_lax_face`
+}
+
+impl Comparison {
+    // Compares the results of two paths into the given set, up to its equivalence. For relations, every outcome
+    // of the left path must be below some outcome of the right path, which is inclusion unless the set is ordered
+    fn holds(&self, set: &dyn SetLike, left: &Rc<dyn Element>, right: &Rc<dyn Element>) -> bool {
+        let (left, right) = (Outcomes::of(left), Outcomes::of(right));
+        match self {
+            Comparison::Equal => {
+                left.is_covered_by(&right, |l, r| set.equivalent(l, r))
+                    && right.is_covered_by(&left, |r, l| set.equivalent(l, r))
+            }
+            Comparison::Below => left.is_covered_by(&right, |l, r| set.le(l, r)),
+        }
+    }
+}
+
+/// An element that fails the property of the set it ends up in
+#[derive(Clone, Debug)]
+pub struct PropertyViolation {
+    pub element: Rc<dyn Element>, // The source element
+    pub set: usize,               // The set whose property fails
+    pub set_name: String,         // Name of that set
+    pub path: Option<PathTrace>, // The path along which the source element was mapped into the set (up to the violating element), or None if it violates its own source set
+}
+
+impl std::fmt::Display for PropertyViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.path {
+            None => write!(
+                f,
+                "Element does not satisfy property of source set {}: {:?}",
+                self.set_name,
+                self.element.name()
+            ),
+            Some(path) => write!(
+                f,
+                "Element does not satisfy property of target set {}: {:?} along path {} with elements in between seen {}, starting from {}",
+                self.set_name,
+                path.intermediates.last().unwrap().name(),
+                path.describe_maps(),
+                path.describe_intermediates(),
+                self.element.name()
+            ),
+        }
+    }
+}
+
+/// An element on which a fallible map failed, see `OnMapError::Report`
+#[derive(Clone, Debug)]
+pub struct MapFailure {
+    pub element: Rc<dyn Element>, // The source element
+    pub map: usize,               // The map that failed
+    pub map_name: String,         // Name of that map
+    pub input: Rc<dyn Element>, // The element the map failed on, which is the source element if the map comes first
+    pub error: String,          // Debug output of the error
+}
+
+impl std::fmt::Display for MapFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Map {} failed on {:?}, starting from {}: {}",
+            self.map_name,
+            self.input.name(),
+            self.element.name(),
+            self.error
+        )
+    }
+}
+
+/// A mistake in the construction of a diagram
+#[derive(Clone, Debug, PartialEq)]
+pub enum DiagramError {
+    DuplicateSet(String), // Two sets were given the same name
+    UnknownSet {
+        map: String,
+        set: String,
+    }, // A map refers to a set name that was never added
+    SetOutOfRange {
+        map: String,
+        set: usize,
+    }, // A map refers to a set index beyond the sets in the diagram
+    DomainMismatch {
+        map: String,
+        set: String,
+        domain: &'static str,
+        elements: &'static str,
+    }, // A map takes elements of another type than its source set holds
+    CodomainMismatch {
+        map: String,
+        set: String,
+        codomain: &'static str,
+        elements: &'static str,
+    }, // A map produces elements of another type than its target set holds
+    MapOutOfRange(usize), // A face refers to a map index beyond the maps in the diagram
+    NotAPath(Vec<String>), // The maps of a face don't compose into a (non-empty) path
+    NotParallel(Vec<String>, Vec<String>), // The two paths of a face don't share their source and target
+    UnknownMap(String),                    // A face refers to a map name that is not in the diagram
+    AmbiguousMap(String), // A face refers to a map name that several maps in the diagram share
+}
+
+impl std::fmt::Display for DiagramError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DiagramError::DuplicateSet(name) => write!(f, "Set {} was added more than once", name),
+            DiagramError::UnknownSet { map, set } => {
+                write!(
+                    f,
+                    "Map {} refers to set {}, which is not in the diagram",
+                    map, set
+                )
+            }
+            DiagramError::SetOutOfRange { map, set } => write!(
+                f,
+                "Map {} refers to set {}, but the diagram has fewer sets",
+                map, set
+            ),
+            DiagramError::DomainMismatch {
+                map,
+                set,
+                domain,
+                elements,
+            } => write!(
+                f,
+                "Map {} takes elements of type {}, but its source set {} holds elements of type {}",
+                map, domain, set, elements
+            ),
+            DiagramError::CodomainMismatch {
+                map,
+                set,
+                codomain,
+                elements,
+            } => write!(
+                f,
+                "Map {} produces elements of type {}, but its target set {} holds elements of type {}",
+                map, codomain, set, elements
+            ),
+            DiagramError::MapOutOfRange(map) => write!(
+                f,
+                "A face refers to map {}, but the diagram has fewer maps",
+                map
+            ),
+            DiagramError::NotAPath(maps) => {
+                write!(f, "Maps {} don't compose into a path", maps.join(" -> "))
+            }
+            DiagramError::NotParallel(left, right) => write!(
+                f,
+                "Paths {} and {} don't share their source and target",
+                left.join(" -> "),
+                right.join(" -> ")
+            ),
+            DiagramError::UnknownMap(map) => write!(
+                f,
+                "A face refers to map {}, which is not in the diagram",
+                map
+            ),
+            DiagramError::AmbiguousMap(map) => write!(
+                f,
+                "A face refers to map {}, but several maps in the diagram have that name",
+                map
+            ),
+        }
+    }
+}
+
+impl Diagram {
+    pub fn new(sets: Vec<Rc<dyn SetLike>>, maps: Vec<Map>) -> Diagram {
+        let set_names = (0..sets.len()).map(|ix| ix.to_string()).collect();
+        let set_positions = vec![None; sets.len()];
+        Diagram {
+            sets,
+            maps,
+            set_names,
+            set_positions,
+            faces: Vec::new(),
+        }
+    }
+
+    // Builds a diagram after validating that every map connects sets in the diagram, and fits the types of their elements
+    pub fn try_new(sets: Vec<Rc<dyn SetLike>>, maps: Vec<Map>) -> Result<Diagram, DiagramError> {
+        let diagram = Diagram::new(sets, maps);
+        diagram.validate()?;
+        Ok(diagram)
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), DiagramError> {
+        for map in self.maps.iter() {
+            for set in [map.from, map.to] {
+                if set >= self.sets.len() {
+                    return Err(DiagramError::SetOutOfRange {
+                        map: map.name.clone(),
+                        set,
+                    });
+                }
+            }
+
+            // Sets that don't know their element type can't be validated
+            if let Some(elements) = self.sets[map.from].element_type() {
+                if map.map.domain() != elements {
+                    return Err(DiagramError::DomainMismatch {
+                        map: map.name.clone(),
+                        set: self.set_name(map.from).to_owned(),
+                        domain: map.map.domain().name(),
+                        elements: elements.name(),
+                    });
+                }
+            }
+
+            if let Some(elements) = self.sets[map.to].element_type() {
+                if map.map.codomain() != elements {
+                    return Err(DiagramError::CodomainMismatch {
+                        map: map.name.clone(),
+                        set: self.set_name(map.to).to_owned(),
+                        codomain: map.map.codomain().name(),
+                        elements: elements.name(),
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Names the sets in order, for rendering the diagram. Sets beyond the given names keep their index as name
+    pub fn with_set_names(mut self, names: &[&str]) -> Diagram {
+        for (set_name, name) in self.set_names.iter_mut().zip(names) {
+            *set_name = name.to_string();
+        }
+        self
+    }
+
+    // Places a set at the given (row, column) when rendering to a grid, e.g. for tikz-cd.
+    // Like extra set names, a hint for a set index beyond the sets in the diagram is ignored.
+    pub fn with_set_position(mut self, set: usize, position: (usize, usize)) -> Diagram {
+        if let Some(hint) = self.set_positions.get_mut(set) {
+            *hint = Some(position);
+        }
+        self
+    }
+
+    // Declares a face: on every element of their common source, both paths must give the same result.
+    // The paths are given as map indices in the order they are applied.
+    // `diagram_faces_commute` checks only the declared faces, while `diagram_commutes` compares all parallel paths first,
+    // and then checks the face directly as well, e.g. if it is longer than the maximum path length
+    pub fn with_face(self, left: &[usize], right: &[usize]) -> Result<Diagram, DiagramError> {
+        self.add_face(left, right, Comparison::Equal)
+    }
+
+    // Declares a face by the names of its maps, which must each name a single map in the diagram
+    pub fn with_named_face(self, left: &[&str], right: &[&str]) -> Result<Diagram, DiagramError> {
+        let left = self.map_indices(left)?;
+        let right = self.map_indices(right)?;
+        self.with_face(&left, &right)
+    }
+
+    // Declares a lax face: on every element of their common source, the `lower` path must end up below the `upper` path
+    // in the order of their target set (see `Set::with_order`), or have fewer outcomes if they pass through relations.
+    // The paths are given as map indices in the order they are applied.
+    // Both paths, and all longer paths through either of them, are then no longer compared for equality with their parallel paths:
+    // the lax face allows its paths to differ, maps out of its target need not preserve the order, and comparing both paths
+    // with a third parallel path would compare them with each other by transitivity. Only the face itself is checked.
+    pub fn with_lax_face(self, lower: &[usize], upper: &[usize]) -> Result<Diagram, DiagramError> {
+        self.add_face(lower, upper, Comparison::Below)
+    }
+
+    // Declares a lax face by the names of its maps, see `Diagram::with_named_face`
+    pub fn with_named_lax_face(
+        self,
+        lower: &[&str],
+        upper: &[&str],
+    ) -> Result<Diagram, DiagramError> {
+        let lower = self.map_indices(lower)?;
+        let upper = self.map_indices(upper)?;
+        self.with_lax_face(&lower, &upper)
+    }
+
+    fn add_face(
+        mut self,
+        left: &[usize],
+        right: &[usize],
+        comparison: Comparison,
+    ) -> Result<Diagram, DiagramError> {
+        let left = self.face_path(left)?;
+        let right = self.face_path(right)?;
+
+        if left.first().unwrap().from() != right.first().unwrap().from()
+            || left.last().unwrap().to() != right.last().unwrap().to()
+        {
+            return Err(DiagramError::NotParallel(
+                self.map_names(&left),
+                self.map_names(&right),
+            ));
+        }
+
+        self.faces.push((left, right, comparison));
+        Ok(self)
+    }
+
+    // Looks up maps by name, for declaring faces
+    pub(crate) fn map_indices(&self, names: &[&str]) -> Result<Vec<usize>, DiagramError> {
+        names
+            .iter()
+            .map(|name| {
+                let mut matching = (0..self.maps.len()).filter(|ix| self.maps[*ix].name == *name);
+                match (matching.next(), matching.next()) {
+                    (Some(ix), None) => Ok(ix),
+                    (None, _) => Err(DiagramError::UnknownMap(name.to_string())),
+                    (Some(_), Some(_)) => Err(DiagramError::AmbiguousMap(name.to_string())),
+                }
+            })
+            .collect()
+    }
+
+    fn face_path(&self, maps: &[usize]) -> Result<Vec<DiEdge>, DiagramError> {
+        let mut path = Vec::new();
+        for ix in maps {
+            let map = self.maps.get(*ix).ok_or(DiagramError::MapOutOfRange(*ix))?;
+            path.push(DiEdge {
+                from: map.from,
+                to: map.to,
+                ix: *ix,
+            });
+        }
+
+        if path.is_empty() || path.windows(2).any(|edges| edges[0].to != edges[1].from) {
+            return Err(DiagramError::NotAPath(self.map_names(&path)));
+        }
+        Ok(path)
+    }
+
+    // The path along the given maps, which are assumed to compose
+    pub(crate) fn path(&self, maps: &[usize]) -> Vec<DiEdge> {
+        maps.iter()
+            .map(|ix| DiEdge {
+                from: self.maps[*ix].from,
+                to: self.maps[*ix].to,
+                ix: *ix,
+            })
+            .collect()
+    }
+
+    fn map_names(&self, path: &[DiEdge]) -> Vec<String> {
+        path.iter()
+            .map(|edge| self.maps[edge.ix].name.clone())
+            .collect()
+    }
+
+    pub fn set_name(&self, set: usize) -> &str {
+        &self.set_names[set]
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum CommutativeDiagramError {
+    CyclicGraphError,
+    PropertyCheckError(PropertyViolation),
+    MapError(MapFailure),
+    WorkerError(String), // A worker of a parallel check failed or found a counterexample, but it could not be reproduced to report it, see `crate::sync`
+}
+
+impl std::fmt::Display for CommutativeDiagramError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CommutativeDiagramError::CyclicGraphError => write!(f, "{}", CyclicGraphError),
+            CommutativeDiagramError::PropertyCheckError(violation) => write!(f, "{}", violation),
+            CommutativeDiagramError::MapError(failure) => write!(f, "{}", failure),
+            CommutativeDiagramError::WorkerError(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+/// Options for `diagram_commutes_with`
+#[derive(Clone, Debug)]
+pub struct CheckOptions {
+    pub max_path_length: Option<usize>, // Only check paths of at most this many maps. Required for cyclic diagrams
+    pub max_counterexamples: Option<usize>, // Stop after finding this many counterexamples, or collect all of them if None
+    pub shrink: bool, // Shrink counterexamples to simpler elements, for source sets that can propose them
+    pub max_shrink_steps: usize, // Stop shrinking a counterexample after trying this many candidates, in case a set keeps proposing them
+    pub time_budget: Option<Duration>, // Stop checking once this much time has passed
+    pub element_budget: Option<usize>, // Stop checking after this many source elements, counted once for every path they are checked on
+    pub faces_only: bool, // Only check the faces declared on the diagram, instead of all parallel paths. See `diagram_faces_commute`
+}
+
+/// Why a check stopped before checking every element
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stopped {
+    Cancelled,     // The observer asked to stop, see `CheckObserver::should_cancel`
+    TimeBudget,    // See `CheckOptions::time_budget`
+    ElementBudget, // See `CheckOptions::element_budget`
+}
+
+/// Follows the progress of a check, see `diagram_commutes_observed`. All events do nothing by default
+pub trait CheckObserver {
+    // A path (given as map indices) is about to be checked on the elements of its source,
+    // against one of the `references` parallel paths found before it
+    fn path_started(&mut self, _path: &[usize], _references: usize) {}
+
+    // The paths of a face are about to be checked on the elements of their source.
+    // Every declared face is checked this way, after any comparison of all parallel paths
+    fn face_started(&mut self, _left: &[usize], _right: &[usize]) {}
+
+    // The paths of a lax face are about to be checked on the elements of their source
+    fn lax_face_started(&mut self, _lower: &[usize], _upper: &[usize]) {}
+
+    // A source element was checked on the current path or face.
+    // `checked_elements` counts the elements encountered in each set so far, as reported in `CommutativeDiagramResult::Commutes`
+    fn element_checked(&mut self, _element: &Rc<dyn Element>, _checked_elements: &[usize]) {}
+
+    fn counterexample_found(&mut self, _counterexample: &Counterexample) {}
+
+    // Polled before every element, the check stops as soon as this returns true
+    fn should_cancel(&mut self) -> bool {
+        false
+    }
+}
+
+impl CheckObserver for () {}
+
+impl Default for CheckOptions {
+    fn default() -> Self {
+        CheckOptions {
+            max_path_length: None,
+            max_counterexamples: Some(1),
+            shrink: true,
+            max_shrink_steps: 1000,
+            time_budget: None,
+            element_budget: None,
+            faces_only: false,
+        }
+    }
+}
+
+pub fn diagram_commutes(
+    diagram: &Diagram,
+) -> Result<CommutativeDiagramResult, CommutativeDiagramError> {
+    diagram_commutes_with(diagram, &CheckOptions::default())
+}
+
+/// Checks commutativity on all paths of at most `max_path_length` maps.
+/// This also supports cyclic diagrams (e.g. isomorphism pairs f: A -> B, g: B -> A), which are checked up to the given depth.
+/// Note that identities are not implicit: to check g . f = id, add an explicit identity map on A.
+pub fn diagram_commutes_bounded(
+    diagram: &Diagram,
+    max_path_length: usize,
+) -> Result<CommutativeDiagramResult, CommutativeDiagramError> {
+    diagram_commutes_with(
+        diagram,
+        &CheckOptions {
+            max_path_length: Some(max_path_length),
+            ..CheckOptions::default()
+        },
+    )
+}
+
+/// Checks commutativity with the given options.
+/// When collecting more than one counterexample, every failing element is reported for every path that disagrees with its reference,
+/// which makes it easier to spot patterns in a broken implementation.
+pub fn diagram_commutes_with(
+    diagram: &Diagram,
+    options: &CheckOptions,
+) -> Result<CommutativeDiagramResult, CommutativeDiagramError> {
+    check(diagram, options, Partition::whole(), &mut ())
+}
+
+/// Checks only the faces declared with `Diagram::with_face` and `Diagram::with_lax_face`, rather than every pair of parallel paths.
+/// The other maps can then be part of the diagram just for context and rendering, and cycles elsewhere in the diagram are no obstacle.
+pub fn diagram_faces_commute(
+    diagram: &Diagram,
+) -> Result<CommutativeDiagramResult, CommutativeDiagramError> {
+    diagram_commutes_with(
+        diagram,
+        &CheckOptions {
+            faces_only: true,
+            ..CheckOptions::default()
+        },
+    )
+}
+
+/// Checks commutativity with the given options, reporting progress to the observer, which can also cancel the check.
+/// A check that is cancelled or runs out of budget without finding a counterexample is `CommutativeDiagramResult::Incomplete`
+pub fn diagram_commutes_observed(
+    diagram: &Diagram,
+    options: &CheckOptions,
+    observer: &mut dyn CheckObserver,
+) -> Result<CommutativeDiagramResult, CommutativeDiagramError> {
+    check(diagram, options, Partition::whole(), observer)
+}
+
+// Checks the diagram on the source elements in the given partition
+pub(crate) fn check(
+    diagram: &Diagram,
+    options: &CheckOptions,
+    partition: Partition,
+    observer: &mut dyn CheckObserver,
+) -> Result<CommutativeDiagramResult, CommutativeDiagramError> {
+    let mut check = Check {
+        diagram,
+        options,
+        partition,
+        observer,
+        started: Instant::now(),
+        elements: 0,
+        stopped: None,
+        checked_elements: vec![0; diagram.sets.len()],
+        counterexamples: Vec::new(),
+    };
+
+    // Paths are enumerated lazily, so a cycle is only reported once the search runs into it
+    if !options.faces_only {
+        match options.max_path_length {
+            Some(max_path_length) => check.paths(diagram.paths_bounded(max_path_length))?,
+            None => check.paths(diagram.paths())?,
+        }
+    }
+
+    // Declared faces are always checked directly, rather than relying on their paths being compared when enumerating them
+    for (left, right, comparison) in diagram.faces.iter() {
+        if check.is_done() {
+            break;
+        }
+
+        let maps = |path: &[DiEdge]| path.iter().map(|edge| edge.ix).collect::<Vec<usize>>();
+        match comparison {
+            Comparison::Equal => check.observer.face_started(&maps(left), &maps(right)),
+            Comparison::Below => check.observer.lax_face_started(&maps(left), &maps(right)),
+        }
+        check.face(left, right, *comparison)?;
+    }
+
+    Ok(check.result())
+}
+
+// The share of the source elements a check covers, so that several workers can split a check between them.
+// See `crate::sync::diagram_commutes_parallel`
+pub(crate) struct Partition<'a> {
+    pub(crate) index: usize, // Elements at this index modulo `count` are checked
+    pub(crate) count: usize,
+    pub(crate) shared: Option<&'a SharedProgress>,
+}
+
+impl Partition<'_> {
+    fn whole() -> Partition<'static> {
+        Partition {
+            index: 0,
+            count: 1,
+            shared: None,
+        }
+    }
+
+    fn includes(&self, element_ix: usize) -> bool {
+        element_ix % self.count == self.index
+    }
+}
+
+// Progress shared between the workers of a check, so that they can all stop once one of them is done
+#[derive(Default)]
+pub(crate) struct SharedProgress {
+    pub(crate) counterexamples: AtomicUsize, // Found by all workers together
+    pub(crate) failed: AtomicBool,           // Whether a worker ran into an error
+}
+
+// The state of a running check
+struct Check<'a> {
+    diagram: &'a Diagram,
+    options: &'a CheckOptions,
+    partition: Partition<'a>,
+    observer: &'a mut dyn CheckObserver,
+    started: Instant,
+    elements: usize, // Source elements checked so far, for `CheckOptions::element_budget`
+    stopped: Option<Stopped>,
+    checked_elements: Vec<usize>,
+    counterexamples: Vec<Counterexample>,
+}
+
+impl Check<'_> {
+    fn paths<I>(&mut self, paths: I) -> Result<(), CommutativeDiagramError>
+    where
+        I: Iterator<Item = Result<Vec<DiEdge>, CyclicGraphError>>,
+    {
+        // References for the paths from the current source, bucketed by their target. Only paths within a bucket are parallel.
+        let mut buckets: HashMap<usize, References> = HashMap::new();
+        let mut source = None;
+        let mut cache = PrefixCache::new();
+
+        for path in paths {
+            let path = path.map_err(|_err| CommutativeDiagramError::CyclicGraphError)?;
+
+            // Paths through lax faces are not compared for equality, see `Diagram::with_lax_face`
+            if self.through_lax_face(&path) {
+                continue;
+            }
+
+            let endpoints = (*path.first().unwrap().from(), *path.last().unwrap().to());
+
+            // Paths are found per source, so once we move on to the next source the cached images and references can be dropped
+            cache.reset_for(endpoints.0);
+            if source != Some(endpoints.0) {
+                buckets.clear();
+                source = Some(endpoints.0);
+            }
+
+            let references = buckets.entry(endpoints.1).or_default();
+            self.observer.path_started(
+                &path.iter().map(|edge| edge.ix).collect::<Vec<usize>>(),
+                references.found,
+            );
+            self.path(&path, references, &mut cache)?;
+            references.found += 1;
+
+            if self.is_done() {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Maps every element of the source through the path and compares the result to a single reference path from its bucket.
+    // By transitivity of equality, this suffices to have all parallel paths agree.
+    // The reference is the first parallel path that is defined on the element, which is usually the first one found,
+    // unless the element is filtered along the way. If there is none yet, this path becomes the reference for the element.
+    fn path(
+        &mut self,
+        path: &[DiEdge],
+        references: &mut References,
+        cache: &mut PrefixCache,
+    ) -> Result<(), CommutativeDiagramError> {
+        let diagram = self.diagram;
+
+        // Kept only if the path is the reference for some element, see below
+        let path_ix = references.paths.len();
+        references.paths.push(path.to_vec());
+        let mut is_reference = false;
+
+        // Find each element of the common source
+        let source_set = &diagram.sets[*path.first().unwrap().from()];
+        let target_set = &diagram.sets[*path.last().unwrap().to()];
+
+        for (element_ix, element) in source_set.elements().enumerate() {
+            if !self.partition.includes(element_ix) {
+                continue;
+            }
+            if self.should_stop() {
+                return Ok(());
+            }
+            if !check_source_element(diagram, *path.first().unwrap().from(), &element)? {
+                continue; // Next!
+            }
+
+            // Even without any parallel paths, each path is evaluated so that the set properties along it are validated
+            let Some(image) = evaluate_path(
+                diagram,
+                element_ix,
+                &element,
+                path,
+                cache,
+                &mut self.checked_elements,
+            )?
+            else {
+                continue;
+            };
+
+            match references.images.get(&element_ix) {
+                None => {
+                    references.images.insert(element_ix, (path_ix, image));
+                    is_reference = true;
+                }
+                // Now, check if the two elements are equal
+                Some((reference_ix, reference_image))
+                    if !Comparison::Equal.holds(
+                        target_set.as_ref(),
+                        reference_image.result(),
+                        image.result(),
+                    ) =>
+                {
+                    let reference = &references.paths[*reference_ix];
+                    self.push(Counterexample::new(
+                        diagram,
+                        element_ix,
+                        &element,
+                        (reference, reference_image.clone()),
+                        (path, image),
+                        Comparison::Equal,
+                    ));
+                    if self.is_done() {
+                        return Ok(());
+                    }
+                }
+                Some(_) => {}
+            }
+
+            self.element_checked(&element);
+        }
+
+        if !is_reference {
+            references.paths.pop();
+        }
+
+        Ok(())
+    }
+
+    // Whether the path contains either path of a lax face, including the case where it is one of them
+    fn through_lax_face(&self, path: &[DiEdge]) -> bool {
+        let contains = |part: &[DiEdge]| path.windows(part.len()).any(|window| window == part);
+        self.diagram.faces.iter().any(|(lower, upper, comparison)| {
+            *comparison == Comparison::Below && (contains(lower) || contains(upper))
+        })
+    }
+
+    // Checks the two paths of a face against each other on every element of their source: they must give the same
+    // result for `Comparison::Equal`, and the left (lower) result must be below the right (upper) one for `Comparison::Below`
+    fn face(
+        &mut self,
+        left: &[DiEdge],
+        right: &[DiEdge],
+        comparison: Comparison,
+    ) -> Result<(), CommutativeDiagramError> {
+        let diagram = self.diagram;
+        let source_set = &diagram.sets[*left.first().unwrap().from()];
+        let target_set = &diagram.sets[*left.last().unwrap().to()];
+        let mut cache = PrefixCache::new();
+
+        for (element_ix, element) in source_set.elements().enumerate() {
+            if !self.partition.includes(element_ix) {
+                continue;
+            }
+            if self.should_stop() {
+                return Ok(());
+            }
+            if !check_source_element(diagram, *left.first().unwrap().from(), &element)? {
+                continue;
+            }
+
+            let Some(left_image) = evaluate_path(
+                diagram,
+                element_ix,
+                &element,
+                left,
+                &mut cache,
+                &mut self.checked_elements,
+            )?
+            else {
+                continue;
+            };
+            let Some(right_image) = evaluate_path(
+                diagram,
+                element_ix,
+                &element,
+                right,
+                &mut cache,
+                &mut self.checked_elements,
+            )?
+            else {
+                continue;
+            };
+
+            if !comparison.holds(
+                target_set.as_ref(),
+                left_image.result(),
+                right_image.result(),
+            ) {
+                self.push(Counterexample::new(
+                    diagram,
+                    element_ix,
+                    &element,
+                    (left, left_image),
+                    (right, right_image),
+                    comparison,
+                ));
+                if self.is_done() {
+                    return Ok(());
+                }
+            }
+
+            self.element_checked(&element);
+        }
+
+        Ok(())
+    }
+
+    fn push(&mut self, counterexample: Counterexample) {
+        if let Some(shared) = self.partition.shared {
+            shared.counterexamples.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let counterexample = if self.options.shrink {
+            shrink_counterexample(self.diagram, counterexample, self.options.max_shrink_steps)
+        } else {
+            counterexample
+        };
+        self.observer.counterexample_found(&counterexample);
+        self.counterexamples.push(counterexample);
+    }
+
+    fn element_checked(&mut self, element: &Rc<dyn Element>) {
+        self.elements += 1;
+        self.observer
+            .element_checked(element, &self.checked_elements);
+    }
+
+    // Whether to stop before the next element, because the check is done, cancelled or out of budget
+    fn should_stop(&mut self) -> bool {
+        if self.stopped.is_none() {
+            if self.observer.should_cancel() {
+                self.stopped = Some(Stopped::Cancelled);
+            } else if self
+                .options
+                .element_budget
+                .is_some_and(|budget| self.elements >= budget)
+            {
+                self.stopped = Some(Stopped::ElementBudget);
+            } else if self
+                .options
+                .time_budget
+                .is_some_and(|budget| self.started.elapsed() >= budget)
+            {
+                self.stopped = Some(Stopped::TimeBudget);
+            }
+        }
+
+        self.is_done()
+    }
+
+    // Whether the check stopped, or enough counterexamples were found by this check or by the others it shares its progress with
+    fn is_done(&self) -> bool {
+        if self.stopped.is_some() {
+            return true;
+        }
+
+        let found = match self.partition.shared {
+            Some(shared) => {
+                if shared.failed.load(Ordering::Relaxed) {
+                    return true;
+                }
+                shared.counterexamples.load(Ordering::Relaxed)
+            }
+            None => self.counterexamples.len(),
+        };
+        self.options
+            .max_counterexamples
+            .is_some_and(|max_counterexamples| found >= max_counterexamples)
+    }
+
+    fn result(self) -> CommutativeDiagramResult {
+        if !self.counterexamples.is_empty() {
+            return CommutativeDiagramResult::DoesNotCommute(self.counterexamples);
+        }
+
+        let checked_elements = self.checked_elements;
+        let path_length_bound = self.options.max_path_length;
+        let seeds = self.diagram.sets.iter().map(|set| set.seed()).collect();
+        let set_names = self.diagram.set_names.clone();
+        match self.stopped {
+            Some(stopped) => CommutativeDiagramResult::Incomplete {
+                stopped,
+                checked_elements,
+                path_length_bound,
+                seeds,
+                set_names,
+            },
+            None => CommutativeDiagramResult::Commutes {
+                checked_elements,
+                path_length_bound,
+                seeds,
+                set_names,
+            },
+        }
+    }
+}
+
+// The parallel paths found so far from one source to one target, with for each source element the first of them
+// that is defined on it and its image there. Only paths that are the reference for some element are kept.
+#[derive(Default)]
+struct References {
+    found: usize,                               // Parallel paths found so far
+    paths: Vec<Vec<DiEdge>>,                    // Paths that are a reference for some element
+    images: HashMap<usize, (usize, PathImage)>, // Source element ix -> (Index into `paths`, Image along it)
+}
+
+// Whether an element of a source set takes part in the check, failing if it violates the property of its set
+fn check_source_element(
+    diagram: &Diagram,
+    set: usize,
+    element: &Rc<dyn Element>,
+) -> Result<bool, CommutativeDiagramError> {
+    // Check if this element should be filtered
+    if !diagram.sets[set].filter(element) {
+        return Ok(false);
+    }
+
+    // Verify the element in the source set.
+    if !diagram.sets[set].check(element) {
+        return Err(CommutativeDiagramError::PropertyCheckError(
+            PropertyViolation {
+                element: element.clone(),
+                set,
+                set_name: diagram.set_name(set).to_owned(),
+                path: None,
+            },
+        ));
+    }
+
+    Ok(true)
+}
+
+// Re-runs the failing pair of paths on simpler candidates proposed by the source set, as long as one of them still fails
+fn shrink_counterexample(
+    diagram: &Diagram,
+    counterexample: Counterexample,
+    max_steps: usize,
+) -> Counterexample {
+    let left = diagram.path(&counterexample.left.maps);
+    let right = diagram.path(&counterexample.right.maps);
+    let source_set = &diagram.sets[*left.first().unwrap().from()];
+    let original = counterexample.element.clone();
+    let original_ix = counterexample.element_ix;
+    let comparison = counterexample.comparison;
+    let mut smallest = counterexample;
+    let mut steps = 0;
+
+    'shrinking: loop {
+        // Candidates are pulled one by one, so a set may base later proposals on earlier ones passing
+        for candidate in source_set.shrink(&smallest.element) {
+            if steps == max_steps {
+                break 'shrinking smallest;
+            }
+            steps += 1;
+
+            // A set that proposes the same element again would otherwise keep shrinking it forever
+            if candidate.eq(&smallest.element) {
+                continue;
+            }
+            if let Some(mut shrunk) =
+                find_counterexample(diagram, original_ix, &candidate, &left, &right, comparison)
+            {
+                shrunk.shrunk_from = Some(original.clone());
+                smallest = shrunk;
+                continue 'shrinking;
+            }
+        }
+
+        break smallest;
+    }
+}
+
+// Finds a counterexample again on an element of the source set, e.g. after a worker of a parallel check found it
+#[cfg(feature = "rayon")]
+pub(crate) fn replay_counterexample(
+    diagram: &Diagram,
+    element_ix: usize,
+    (left, right): (&[usize], &[usize]),
+    comparison: Comparison,
+    options: &CheckOptions,
+) -> Option<Counterexample> {
+    let (left, right) = (diagram.path(left), diagram.path(right));
+    let element = diagram.sets[*left.first()?.from()]
+        .elements()
+        .nth(element_ix)?;
+
+    let counterexample =
+        find_counterexample(diagram, element_ix, &element, &left, &right, comparison)?;
+    Some(if options.shrink {
+        shrink_counterexample(diagram, counterexample, options.max_shrink_steps)
+    } else {
+        counterexample
+    })
+}
+
+// Checks a single element on a pair of paths. Candidates that are filtered or fail a set property are not counterexamples
+fn find_counterexample(
+    diagram: &Diagram,
+    element_ix: usize,
+    element: &Rc<dyn Element>,
+    left: &[DiEdge],
+    right: &[DiEdge],
+    comparison: Comparison,
+) -> Option<Counterexample> {
+    let source_set = &diagram.sets[*left.first().unwrap().from()];
+    if !source_set.filter(element) || !source_set.check(element) {
+        return None;
+    }
+
+    // The candidate is not part of the source set, so it gets a cache (and counters) of its own
+    let mut cache = PrefixCache::new();
+    let mut checked_elements = vec![0; diagram.sets.len()];
+
+    let left_image =
+        evaluate_path(diagram, 0, element, left, &mut cache, &mut checked_elements).ok()??;
+    let right_image = evaluate_path(
+        diagram,
+        0,
+        element,
+        right,
+        &mut cache,
+        &mut checked_elements,
+    )
+    .ok()??;
+
+    let target_set = &diagram.sets[*left.last().unwrap().to()];
+    if comparison.holds(
+        target_set.as_ref(),
+        left_image.result(),
+        right_image.result(),
+    ) {
+        return None;
+    }
+
+    Some(Counterexample::new(
+        diagram,
+        element_ix,
+        element,
+        (left, left_image),
+        (right, right_image),
+        comparison,
+    ))
+}
+
+// The image of an element along a path, with all elements seen along the way (including the final one)
+#[derive(Clone)]
+struct PathImage {
+    intermediates: Vec<Rc<dyn Element>>,
+    result: Rc<dyn Element>, // The final element, normalised if the path ends in a quotient set
+}
+
+impl PathImage {
+    fn result(&self) -> &Rc<dyn Element> {
+        &self.result
+    }
+}
+
+// Memoises the images of the elements of a single source set along the prefixes of the last path evaluated.
+// Paths are enumerated depth-first, so paths sharing a prefix (e.g. f -> g -> h and f -> g -> k) come one after another
+// and share the work along it, so that each map is applied at most once per source element and prefix.
+// Evaluating a path drops the images along the prefixes it leaves, so only those along a single path are held in memory.
+struct PrefixCache {
+    source: Option<usize>,
+    prefixes: Vec<PrefixNode>, // The prefixes of the last path evaluated, from its first map onwards
+}
+
+struct PrefixNode {
+    map: usize,                                      // The last map of the prefix
+    images: HashMap<usize, Option<Rc<dyn Element>>>, // Source element ix -> Image along this prefix, or None if it was filtered
+}
+
+impl PrefixCache {
+    fn new() -> PrefixCache {
+        PrefixCache {
+            source: None,
+            prefixes: Vec::new(),
+        }
+    }
+
+    fn reset_for(&mut self, source: usize) {
+        if self.source != Some(source) {
+            self.source = Some(source);
+            self.prefixes.clear();
+        }
+    }
+
+    // Extends the prefix of the given length with a map, dropping the longer prefixes if they continue with another map
+    fn extend(&mut self, length: usize, map: usize) {
+        if self
+            .prefixes
+            .get(length)
+            .is_some_and(|node| node.map == map)
+        {
+            return;
+        }
+
+        self.prefixes.truncate(length);
+        self.prefixes.push(PrefixNode {
+            map,
+            images: HashMap::new(),
         });
     }
 }
+
+// Maps an element along a path, reusing the images along any prefix that was mapped before.
+// If the element is filtered along the path, or a map along it is not defined on it, there is nothing to compare and `None` is returned.
+fn evaluate_path(
+    diagram: &Diagram,
+    element_ix: usize,
+    element: &Rc<dyn Element>,
+    path: &[DiEdge],
+    cache: &mut PrefixCache,
+    checked_elements: &mut [usize],
+) -> Result<Option<PathImage>, CommutativeDiagramError> {
+    let mut path_element = element.clone();
+    let mut intermediates: Vec<Rc<dyn Element>> = Vec::new();
+
+    for (node, edge) in path.iter().enumerate() {
+        cache.extend(node, edge.ix);
+        match cache.prefixes[node].images.get(&element_ix) {
+            Some(Some(image)) => {
+                path_element = image.clone();
+                intermediates.push(path_element.clone());
+                continue;
+            }
+            Some(None) => return Ok(None),
+            None => {}
+        }
+
+        let map = &diagram.maps[edge.ix].map;
+        let set = &diagram.sets[*edge.to()];
+
+        path_element = match apply(map.as_ref(), &path_element) {
+            Ok(image) => image,
+            Err(Unmapped::WrongType) => panic!(
+                "Map applied to an element of the wrong type, use Diagram::try_new to validate the diagram"
+            ),
+            Err(Unmapped::Excluded) => {
+                cache.prefixes[node].images.insert(element_ix, None);
+                return Ok(None);
+            }
+            Err(Unmapped::Failed(error)) => {
+                return Err(CommutativeDiagramError::MapError(MapFailure {
+                    element: element.clone(),
+                    map: edge.ix,
+                    map_name: diagram.maps[edge.ix].name.clone(),
+                    input: path_element,
+                    error,
+                }))
+            }
+        };
+
+        // Record that we found an element
+        checked_elements[*edge.to()] += 1;
+
+        // Check if this element/path should be filtered
+        let Some(filtered) = filter_element(set.as_ref(), &path_element) else {
+            cache.prefixes[node].images.insert(element_ix, None);
+            return Ok(None);
+        };
+        path_element = filtered;
+        intermediates.push(path_element.clone());
+
+        // Check if this element passes validation
+        if !Outcomes::of(&path_element)
+            .elements()
+            .iter()
+            .all(|outcome| set.check(outcome))
+        {
+            return Err(CommutativeDiagramError::PropertyCheckError(
+                PropertyViolation {
+                    element: element.clone(),
+                    set: *edge.to(),
+                    set_name: diagram.set_name(*edge.to()).to_owned(),
+                    path: Some(PathTrace::new(diagram, path, intermediates)),
+                },
+            ));
+        }
+
+        cache.prefixes[node]
+            .images
+            .insert(element_ix, Some(path_element.clone()));
+    }
+
+    // Only the end of the path is normalised, as the maps out of a quotient set take its elements as they are
+    let target_set = &diagram.sets[*path.last().unwrap().to()];
+    let result = match path_element.as_any().downcast_ref::<Outcomes>() {
+        Some(outcomes) => Rc::new(Outcomes::new(
+            outcomes
+                .elements()
+                .iter()
+                .map(|outcome| target_set.normalise(outcome)),
+        )),
+        None => target_set.normalise(&path_element),
+    };
+
+    Ok(Some(PathImage {
+        intermediates,
+        result,
+    }))
+}
+
+// Applies a map to an element, or to each of the outcomes of the relations along the path so far
+fn apply(map: &dyn Mappable, element: &Rc<dyn Element>) -> Result<Rc<dyn Element>, Unmapped> {
+    let Some(outcomes) = element.as_any().downcast_ref::<Outcomes>() else {
+        return map.map(element);
+    };
+
+    let mut images = Vec::new();
+    for outcome in outcomes.elements() {
+        match map.map(outcome) {
+            Ok(image) => images.extend(Outcomes::of(&image).0),
+            // Composing relations drops the outcomes a map is not defined on
+            Err(Unmapped::Excluded) => {}
+            Err(unmapped) => return Err(unmapped),
+        }
+    }
+    Ok(Rc::new(Outcomes::new(images)))
+}
+
+// Filters an element, or each of the outcomes of relations individually. Returns None if the element is filtered
+fn filter_element(set: &dyn SetLike, element: &Rc<dyn Element>) -> Option<Rc<dyn Element>> {
+    match element.as_any().downcast_ref::<Outcomes>() {
+        Some(outcomes) => Some(Rc::new(Outcomes::new(
+            outcomes
+                .elements()
+                .iter()
+                .filter(|outcome| set.filter(outcome))
+                .cloned(),
+        ))),
+        None => set.filter(element).then(|| element.clone()),
+    }
+}
+
+impl std::fmt::Debug for dyn Element {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl<T> Element for T
+where
+    T: Clone + PartialEq + Sized + 'static + core::fmt::Debug,
+{
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn eq(&self, other: &Rc<dyn Element>) -> bool {
+        if let Some(other) = other.as_any().downcast_ref::<T>() {
+            self == other
+        } else {
+            false
+        }
+    }
+
+    fn name(&self) -> String {
+        format!("{:#?}", self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_addition_is_associative_on_integers() {
+        use itertools::Itertools;
+
+        let generating_integers: Vec<i32> = (0..20).collect();
+
+        let left_add = |(a, b, c): &(i32, i32, i32)| (a + b, *c);
+        let right_add = |(a, b, c): &(i32, i32, i32)| (*a, b + c);
+
+        // Build the sets (triplets, pairs, integers) so that summing operations can be well-defined later
+        let triplets: Vec<(i32, i32, i32)> = (generating_integers)
+            .clone()
+            .iter()
+            .cartesian_product(generating_integers.clone())
+            .cartesian_product(generating_integers.clone().iter())
+            .map(|((a, b), c)| (*a, b, *c))
+            .collect();
+
+        let diagram = Diagram::new(
+            vec![
+                Set::new(triplets),
+                Set::<(i32, i32), _, _>::new_no_generating_set_filtered(|(a, b): &(i32, i32)| {
+                    a + b >= 6
+                }),
+                Set::<(i32, i32), _, _>::new_no_generating_set_filtered(|(a, b): &(i32, i32)| {
+                    a + b >= 5
+                }),
+                Set::<i32, _, _>::new_no_generating_set_checked(|x: &i32| *x >= 5),
+            ],
+            vec![
+                Map::new(0, 1, left_add, "(+,id)"),
+                Map::new(0, 2, right_add, "(id,+)"),
+                Map::new(2, 3, |(a, b): &(i32, i32)| a + b, "(+)"),
+                Map::new(1, 3, |(a, b): &(i32, i32)| a + b, "(+)"),
+            ],
+        );
+
+        assert!(match diagram_commutes(&diagram).unwrap() {
+            CommutativeDiagramResult::Commutes { .. } => true,
+            CommutativeDiagramResult::DoesNotCommute(counterexamples) => {
+                panic!("{}", counterexamples[0])
+            }
+            CommutativeDiagramResult::Incomplete { stopped, .. } =>
+                panic!("Stopped early: {:?}", stopped),
+        });
+    }
+
+    #[test]
+    fn test_isomorphism_pair_in_cyclic_diagram() {
+        let diagram = Diagram::new(
+            vec![
+                Set::new((-10..10).collect::<Vec<i32>>()),
+                Set::<String, _, _>::new_no_generating_set(),
+            ],
+            vec![
+                Map::new(0, 0, |x: &i32| *x, "id"),
+                Map::new(0, 1, |x: &i32| x.to_string(), "to_string"),
+                Map::new(1, 0, |x: &String| x.parse::<i32>().unwrap(), "parse"),
+            ],
+        );
+
+        // Unbounded checking refuses cyclic diagrams
+        assert!(matches!(
+            diagram_commutes(&diagram),
+            Err(CommutativeDiagramError::CyclicGraphError)
+        ));
+
+        match diagram_commutes_bounded(&diagram, 4).unwrap() {
+            CommutativeDiagramResult::Commutes {
+                path_length_bound, ..
+            } => assert_eq!(path_length_bound, Some(4)),
+            CommutativeDiagramResult::DoesNotCommute(counterexamples) => {
+                panic!("{}", counterexamples[0])
+            }
+            CommutativeDiagramResult::Incomplete { stopped, .. } => {
+                panic!("Stopped early: {:?}", stopped)
+            }
+        }
+
+        // Replacing the inverse by something else breaks g . f = id
+        let diagram = Diagram::new(
+            vec![
+                Set::new((-10..10).collect::<Vec<i32>>()),
+                Set::<String, _, _>::new_no_generating_set(),
+            ],
+            vec![
+                Map::new(0, 0, |x: &i32| *x, "id"),
+                Map::new(0, 1, |x: &i32| x.to_string(), "to_string"),
+                Map::new(1, 0, |x: &String| x.len() as i32, "len"),
+            ],
+        );
+
+        assert!(matches!(
+            diagram_commutes_bounded(&diagram, 2).unwrap(),
+            CommutativeDiagramResult::DoesNotCommute(_)
+        ));
+    }
+
+    #[test]
+    fn test_counterexample_found_before_enumerating_all_paths() {
+        // Two distinct endomorphisms give 2^40 paths of length up to 40, which can only be checked lazily
+        let diagram = Diagram::new(
+            vec![Set::new(vec![0u64, 1, 2])],
+            vec![
+                Map::new(0, 0, |x: &u64| x + 1, "succ"),
+                Map::new(0, 0, |x: &u64| x + 2, "succ . succ"),
+            ],
+        );
+
+        assert!(matches!(
+            diagram_commutes_bounded(&diagram, 40).unwrap(),
+            CommutativeDiagramResult::DoesNotCommute(_)
+        ));
+    }
+
+    #[test]
+    fn test_parallel_paths_compared_where_reference_is_filtered() {
+        // The first path from 0 to 2 goes through a set that filters out everything from 5 onwards.
+        // The other two paths disagree exactly there, so they must be compared with each other.
+        let diagram = Diagram::new(
+            vec![
+                Set::new((0..10).collect::<Vec<i32>>()),
+                Set::<i32, _, _>::new_no_generating_set_filtered(|x: &i32| *x < 5),
+                Set::<i32, _, _>::new_no_generating_set(),
+            ],
+            vec![
+                Map::new(0, 1, |x: &i32| *x, "f"),
+                Map::new(1, 2, |x: &i32| x * 2, "g"),
+                Map::new(0, 2, |x: &i32| x * 2, "h"),
+                Map::new(0, 2, |x: &i32| if *x < 5 { x * 2 } else { 0 }, "k"),
+            ],
+        );
+
+        match diagram_commutes(&diagram).unwrap() {
+            CommutativeDiagramResult::Commutes { .. }
+            | CommutativeDiagramResult::Incomplete { .. } => {
+                panic!("h and k disagree from 5 onwards")
+            }
+            CommutativeDiagramResult::DoesNotCommute(counterexamples) => {
+                assert!(counterexamples[0]
+                    .to_string()
+                    .starts_with("h and k from 0 to 2 don't agree on 5"))
+            }
+        }
+    }
+
+    #[test]
+    fn test_parallel_paths_compared_where_many_earlier_paths_are_filtered() {
+        // Eight paths from 0 to 2 go through a set that filters out everything, so only h and k are defined
+        let mut maps: Vec<Map> = (0..8)
+            .map(|ix| Map::new(0, 1, |x: &i32| *x, &format!("f_{}", ix)))
+            .collect();
+        maps.push(Map::new(1, 2, |x: &i32| *x, "g"));
+        maps.push(Map::new(0, 2, |x: &i32| *x, "h"));
+        maps.push(Map::new(0, 2, |x: &i32| -x, "k"));
+        let diagram = Diagram::new(
+            vec![
+                Set::new((0..10).collect::<Vec<i32>>()),
+                Set::<i32, _, _>::new_no_generating_set_filtered(|_: &i32| false),
+                Set::<i32, _, _>::new_no_generating_set(),
+            ],
+            maps,
+        );
+
+        match diagram_commutes(&diagram).unwrap() {
+            CommutativeDiagramResult::Commutes { .. }
+            | CommutativeDiagramResult::Incomplete { .. } => {
+                panic!("h and k disagree from 1 onwards")
+            }
+            CommutativeDiagramResult::DoesNotCommute(counterexamples) => {
+                assert!(counterexamples[0]
+                    .to_string()
+                    .starts_with("h and k from 0 to 2 don't agree on 1"))
+            }
+        }
+
+        // Declaring h and k as a face doesn't change that
+        let face = diagram.with_named_face(&["h"], &["k"]).unwrap();
+        assert!(matches!(
+            diagram_commutes(&face).unwrap(),
+            CommutativeDiagramResult::DoesNotCommute(_)
+        ));
+        assert!(matches!(
+            diagram_faces_commute(&face).unwrap(),
+            CommutativeDiagramResult::DoesNotCommute(_)
+        ));
+    }
+
+    #[test]
+    fn test_shared_prefixes_are_mapped_once() {
+        use std::cell::Cell;
+
+        let applications = Rc::new(Cell::new(0));
+        let counter = applications.clone();
+        let expensive = move |x: &i32| {
+            counter.set(counter.get() + 1);
+            x + 1
+        };
+
+        // f is a common prefix of the paths f -> g and f -> k (and of f itself), but should only run once per element
+        let diagram = Diagram::new(
+            vec![
+                Set::new((0..10).collect::<Vec<i32>>()),
+                Set::<i32, _, _>::new_no_generating_set(),
+                Set::<i32, _, _>::new_no_generating_set(),
+            ],
+            vec![
+                Map::new(0, 1, expensive, "f"),
+                Map::new(1, 2, |x: &i32| x * 2, "g"),
+                Map::new(1, 2, |x: &i32| x + x, "k"),
+            ],
+        );
+
+        assert!(matches!(
+            diagram_commutes(&diagram).unwrap(),
+            CommutativeDiagramResult::Commutes { .. }
+        ));
+        assert_eq!(applications.get(), 10);
+    }
+
+    #[test]
+    fn test_prefix_cache_only_holds_the_current_path() {
+        // Two equal idempotent endomorphisms give 2^11 - 2 commuting paths of length up to 10
+        let diagram = Diagram::new(
+            vec![Set::new((0..10).collect::<Vec<u64>>())],
+            vec![
+                Map::new(0, 0, |x: &u64| (*x).min(5), "min"),
+                Map::new(0, 0, |x: &u64| 5.min(*x), "min'"),
+            ],
+        );
+
+        assert!(matches!(
+            diagram_commutes_bounded(&diagram, 10).unwrap(),
+            CommutativeDiagramResult::Commutes { .. }
+        ));
+
+        // Evaluating the paths in the order the check does, the cache never holds more than the prefixes of the last one
+        let mut cache = PrefixCache::new();
+        let mut checked_elements = vec![0];
+        for path in diagram.paths_bounded(10) {
+            let path = path.unwrap();
+            for (element_ix, element) in diagram.sets[0].elements().enumerate() {
+                evaluate_path(
+                    &diagram,
+                    element_ix,
+                    &element,
+                    &path,
+                    &mut cache,
+                    &mut checked_elements,
+                )
+                .unwrap();
+            }
+
+            assert_eq!(cache.prefixes.len(), path.len());
+            assert!(cache
+                .prefixes
+                .iter()
+                .zip(path.iter())
+                .all(|(node, edge)| node.map == edge.ix && node.images.len() == 10));
+        }
+
+        // Each map is applied once per element and prefix, i.e. once per element and path
+        assert_eq!(checked_elements, vec![10 * ((1 << 11) - 2)]);
+    }
+
+    #[test]
+    fn test_collect_all_counterexamples() {
+        // Absolute value and identity only disagree on negative inputs
+        let diagram = Diagram::new(
+            vec![
+                Set::new((-5..5).collect::<Vec<i32>>()),
+                Set::<i32, _, _>::new_no_generating_set(),
+            ],
+            vec![
+                Map::new(0, 1, |x: &i32| *x, "id"),
+                Map::new(0, 1, |x: &i32| x.abs(), "abs"),
+            ],
+        );
+
+        let all = CheckOptions {
+            max_counterexamples: None,
+            ..CheckOptions::default()
+        };
+        match diagram_commutes_with(&diagram, &all).unwrap() {
+            CommutativeDiagramResult::Commutes { .. }
+            | CommutativeDiagramResult::Incomplete { .. } => {
+                panic!("abs is not the identity")
+            }
+            CommutativeDiagramResult::DoesNotCommute(counterexamples) => {
+                assert_eq!(counterexamples.len(), 5);
+
+                let first = &counterexamples[0];
+                assert_eq!(first.left.maps, vec![0]);
+                assert_eq!(first.right.map_names, vec!["abs".to_owned()]);
+                assert_eq!(first.element.as_any().downcast_ref::<i32>(), Some(&-5));
+                assert_eq!(first.left_result.as_any().downcast_ref::<i32>(), Some(&-5));
+                assert_eq!(first.right_result.as_any().downcast_ref::<i32>(), Some(&5));
+                assert!(counterexamples[4]
+                    .to_string()
+                    .starts_with("id and abs from 0 to 1 don't agree on -1"));
+            }
+        }
+
+        let capped = CheckOptions {
+            max_counterexamples: Some(2),
+            ..CheckOptions::default()
+        };
+        match diagram_commutes_with(&diagram, &capped).unwrap() {
+            CommutativeDiagramResult::Commutes { .. }
+            | CommutativeDiagramResult::Incomplete { .. } => {
+                panic!("abs is not the identity")
+            }
+            CommutativeDiagramResult::DoesNotCommute(counterexamples) => {
+                assert_eq!(counterexamples.len(), 2)
+            }
+        }
+    }
+
+    #[test]
+    fn test_counterexamples_are_shrunk() {
+        // Iterating from the top, the first failing element is 99, but the smallest one is 37.
+        // Shrinkers of single-threaded sets can keep state in an `Rc`, here to count the shrinking steps
+        let steps = Rc::new(std::cell::Cell::new(0));
+        let shrink_steps = steps.clone();
+        let diagram = Diagram::new(
+            vec![
+                Set::new((0..100).rev().collect::<Vec<i32>>()).with_shrinker(move |x: &i32| {
+                    shrink_steps.set(shrink_steps.get() + 1);
+                    if *x > 0 {
+                        vec![x / 2, x - 1]
+                    } else {
+                        vec![]
+                    }
+                }),
+                Set::<i32, _, _>::new_no_generating_set(),
+            ],
+            vec![
+                Map::new(0, 1, |x: &i32| *x, "id"),
+                Map::new(0, 1, |x: &i32| if *x < 37 { *x } else { 0 }, "truncate"),
+            ],
+        );
+
+        match diagram_commutes(&diagram).unwrap() {
+            CommutativeDiagramResult::Commutes { .. }
+            | CommutativeDiagramResult::Incomplete { .. } => {
+                panic!("truncate is not the identity")
+            }
+            CommutativeDiagramResult::DoesNotCommute(counterexamples) => {
+                let counterexample = &counterexamples[0];
+                assert_eq!(
+                    counterexample.element.as_any().downcast_ref::<i32>(),
+                    Some(&37)
+                );
+                assert_eq!(
+                    counterexample
+                        .shrunk_from
+                        .as_ref()
+                        .and_then(|e| e.as_any().downcast_ref::<i32>()),
+                    Some(&99)
+                );
+            }
+        }
+        assert!(steps.get() > 0);
+
+        let no_shrinking = CheckOptions {
+            shrink: false,
+            ..CheckOptions::default()
+        };
+        match diagram_commutes_with(&diagram, &no_shrinking).unwrap() {
+            CommutativeDiagramResult::Commutes { .. }
+            | CommutativeDiagramResult::Incomplete { .. } => {
+                panic!("truncate is not the identity")
+            }
+            CommutativeDiagramResult::DoesNotCommute(counterexamples) => {
+                assert_eq!(
+                    counterexamples[0].element.as_any().downcast_ref::<i32>(),
+                    Some(&99)
+                );
+                assert!(counterexamples[0].shrunk_from.is_none());
+            }
+        }
+    }
+
+    #[test]
+    fn test_shrinking_stops() {
+        let diagram = |shrink: fn(&i32) -> Vec<i32>| {
+            Diagram::new(
+                vec![
+                    Set::new(vec![10]).with_shrinker(shrink),
+                    Set::<i32, _, _>::new_no_generating_set(),
+                ],
+                vec![
+                    Map::new(0, 1, |x: &i32| *x, "id"),
+                    Map::new(0, 1, |x: &i32| -x, "negate"),
+                ],
+            )
+        };
+        let shrunk = |diagram: &Diagram, options: &CheckOptions| match diagram_commutes_with(
+            diagram, options,
+        )
+        .unwrap()
+        {
+            CommutativeDiagramResult::Commutes { .. }
+            | CommutativeDiagramResult::Incomplete { .. } => {
+                panic!("negate is not the identity")
+            }
+            CommutativeDiagramResult::DoesNotCommute(counterexamples) => *counterexamples[0]
+                .element
+                .as_any()
+                .downcast_ref::<i32>()
+                .unwrap(),
+        };
+
+        // Proposing the element itself is no progress
+        assert_eq!(shrunk(&diagram(|x| vec![*x]), &CheckOptions::default()), 10);
+
+        // Every proposal fails, so shrinking only stops at the maximum number of steps
+        let options = CheckOptions {
+            max_shrink_steps: 5,
+            ..CheckOptions::default()
+        };
+        assert_eq!(shrunk(&diagram(|x| vec![x + 1]), &options), 15);
+    }
+
+    #[cfg(feature = "quickcheck")]
+    #[test]
+    fn test_arbitrary_set_reports_seed() {
+        let diagram = Diagram::new(
+            vec![
+                Set::<i32, _, _>::new_arbitrary_seeded(100, 3),
+                Set::<i32, _, _>::new_no_generating_set(),
+            ],
+            vec![
+                Map::new(0, 1, |x: &i32| x.wrapping_abs(), "abs"),
+                Map::new(0, 1, |x: &i32| *x, "id"),
+            ],
+        );
+
+        match diagram_commutes(&diagram).unwrap() {
+            CommutativeDiagramResult::Commutes { .. }
+            | CommutativeDiagramResult::Incomplete { .. } => {
+                panic!("abs is not the identity")
+            }
+            CommutativeDiagramResult::DoesNotCommute(counterexamples) => {
+                assert_eq!(counterexamples[0].seed, Some(3));
+
+                // quickcheck shrinks integers towards zero, so the smallest negative number is found
+                assert_eq!(
+                    counterexamples[0].element.as_any().downcast_ref::<i32>(),
+                    Some(&-1)
+                );
+            }
+        }
+
+        let diagram = Diagram::new(
+            vec![
+                Set::<i32, _, _>::new_arbitrary(100),
+                Set::<i32, _, _>::new_no_generating_set(),
+            ],
+            vec![Map::new(0, 1, |x: &i32| x.wrapping_abs(), "abs")],
+        );
+
+        match diagram_commutes(&diagram).unwrap() {
+            CommutativeDiagramResult::Commutes { seeds, .. } => {
+                assert!(seeds[0].is_some());
+                assert!(seeds[1].is_none());
+            }
+            CommutativeDiagramResult::DoesNotCommute(counterexamples) => {
+                panic!("{}", counterexamples[0])
+            }
+            CommutativeDiagramResult::Incomplete { stopped, .. } => {
+                panic!("Stopped early: {:?}", stopped)
+            }
+        }
+
+        // Every run gets a seed of its own
+        let seeds: std::collections::HashSet<Option<u64>> = (0..100)
+            .map(|_| Set::<i32, _, _>::new_arbitrary(1).seed())
+            .collect();
+        assert_eq!(seeds.len(), 100);
+    }
+
+    #[test]
+    fn test_try_new_validates_maps() {
+        let sets = || -> Vec<Rc<dyn SetLike>> {
+            vec![
+                Set::new(vec![1, 2, 3]),
+                Set::<String, _, _>::new_no_generating_set(),
+            ]
+        };
+
+        assert!(Diagram::try_new(
+            sets(),
+            vec![Map::new(0, 1, |x: &i32| x.to_string(), "to_string")]
+        )
+        .is_ok());
+
+        assert_eq!(
+            Diagram::try_new(sets(), vec![Map::new(0, 2, |x: &i32| *x, "id")]).err(),
+            Some(DiagramError::SetOutOfRange {
+                map: "id".to_owned(),
+                set: 2
+            })
+        );
+
+        assert_eq!(
+            Diagram::try_new(sets(), vec![Map::new(1, 0, |x: &i32| *x, "id")]).err(),
+            Some(DiagramError::DomainMismatch {
+                map: "id".to_owned(),
+                set: "1".to_owned(),
+                domain: "i32",
+                elements: "alloc::string::String"
+            })
+        );
+
+        let error = Diagram::try_new(sets(), vec![Map::new(0, 1, |x: &i32| *x as i64, "widen")])
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "Map widen produces elements of type i64, but its target set 1 holds elements of type alloc::string::String"
+        );
+    }
+
+    #[test]
+    fn test_mismatches_refer_to_sets_by_name() {
+        let mistyped = crate::builder::DiagramBuilder::new()
+            .set("A", Set::new(vec![1]))
+            .set("B", Set::new(vec![2]))
+            .map("A", "B", |x: &String| x.len() as i32, "len")
+            .build();
+        assert!(matches!(
+            mistyped.err(),
+            Some(DiagramError::DomainMismatch { set, .. }) if set == "A"
+        ));
+    }
+
+    #[test]
+    fn test_fallible_maps() {
+        // Dividing and then multiplying back only gives the numerator when the division is exact and defined
+        let diagram = |on_error| {
+            Diagram::new(
+                vec![
+                    Set::new(vec![(6, 3), (4, 2), (1, 0), (0, 0)]),
+                    Set::<i32, _, _>::new_no_generating_set(),
+                    Set::<i32, _, _>::new_no_generating_set(),
+                ],
+                vec![
+                    Map::new_fallible(
+                        0,
+                        1,
+                        |(a, b): &(i32, i32)| {
+                            a.checked_div(*b).map(|q| q * b).ok_or("division by zero")
+                        },
+                        "div",
+                        on_error,
+                    ),
+                    Map::new(1, 2, |x: &i32| *x, "id"),
+                    Map::new(0, 2, |(a, _b): &(i32, i32)| *a, "fst"),
+                ],
+            )
+        };
+
+        assert!(matches!(
+            diagram_commutes(&diagram(OnMapError::Exclude)).unwrap(),
+            CommutativeDiagramResult::Commutes { .. }
+        ));
+
+        match diagram_commutes(&diagram(OnMapError::Report)) {
+            Err(CommutativeDiagramError::MapError(failure)) => {
+                assert_eq!(failure.map, 0);
+                assert_eq!(failure.map_name, "div");
+                assert_eq!(
+                    failure.element.as_any().downcast_ref::<(i32, i32)>(),
+                    Some(&(1, 0))
+                );
+                assert_eq!(failure.error, "\"division by zero\"");
+            }
+            _ => panic!("Division by zero should be reported"),
+        }
+    }
+
+    #[test]
+    fn test_partial_maps_only_exclude_their_own_paths() {
+        // x * x / x panics on 0, so it is declared partial. The total maps into the same set are still checked on 0
+        let diagram = Diagram::new(
+            vec![
+                Set::new((-3..=3).collect::<Vec<i32>>()),
+                Set::<i32, _, _>::new_no_generating_set(),
+            ],
+            vec![
+                Map::new(0, 1, |x: &i32| *x, "id"),
+                Map::new_partial(0, 1, |x: &i32| x * x / x, |x: &i32| *x != 0, "square / x"),
+                Map::new(0, 1, |x: &i32| x.signum() * x.abs(), "sign * abs"),
+            ],
+        );
+
+        match diagram_commutes(&diagram).unwrap() {
+            CommutativeDiagramResult::Commutes {
+                checked_elements, ..
+            } => assert_eq!(checked_elements, vec![0, 7 + 6 + 7]),
+            CommutativeDiagramResult::DoesNotCommute(counterexamples) => {
+                panic!("{}", counterexamples[0])
+            }
+            CommutativeDiagramResult::Incomplete { stopped, .. } => {
+                panic!("Stopped early: {:?}", stopped)
+            }
+        }
+    }
+
+    #[test]
+    fn test_relations_and_lax_faces() {
+        // Taking one or two steps, twice, reaches the same outcomes as jumping two to four steps at once
+        let diagram = |jump: fn(&i32) -> Vec<i32>| {
+            Diagram::new(
+                vec![
+                    Set::new((0..5).collect::<Vec<i32>>()),
+                    Set::<i32, _, _>::new_no_generating_set(),
+                    Set::<i32, _, _>::new_no_generating_set(),
+                ],
+                vec![
+                    Map::new_relation(0, 1, |x: &i32| vec![x + 1, x + 2], "step"),
+                    Map::new_relation(1, 2, |x: &i32| vec![x + 1, x + 2], "step"),
+                    Map::new_relation(0, 2, jump, "jump"),
+                ],
+            )
+        };
+
+        assert!(matches!(
+            diagram_commutes(&diagram(|x| vec![x + 4, x + 3, x + 2, x + 3])).unwrap(),
+            CommutativeDiagramResult::Commutes { .. }
+        ));
+
+        // Jumping only an even number of steps reaches fewer outcomes, which only commutes laxly
+        let even = |x: &i32| vec![x + 2, x + 4];
+        assert!(matches!(
+            diagram_commutes(&diagram(even)).unwrap(),
+            CommutativeDiagramResult::DoesNotCommute(_)
+        ));
+        assert!(matches!(
+            diagram_commutes(&diagram(even).with_lax_face(&[2], &[0, 1]).unwrap()).unwrap(),
+            CommutativeDiagramResult::Commutes { .. }
+        ));
+
+        match diagram_commutes(&diagram(even).with_lax_face(&[0, 1], &[2]).unwrap()).unwrap() {
+            CommutativeDiagramResult::Commutes { .. }
+            | CommutativeDiagramResult::Incomplete { .. } => {
+                panic!("Stepping reaches odd outcomes")
+            }
+            CommutativeDiagramResult::DoesNotCommute(counterexamples) => {
+                assert_eq!(counterexamples[0].comparison, Comparison::Below);
+                assert!(counterexamples[0]
+                    .to_string()
+                    .starts_with("step -> step is not below jump from 0 to 2 on 0. Left gets {2, 3, 4} while right gets {2, 4}"));
+            }
+        }
+
+        assert_eq!(
+            diagram(even).with_lax_face(&[0, 2], &[2]).err(),
+            Some(DiagramError::NotAPath(vec![
+                "step".to_owned(),
+                "jump".to_owned()
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_lax_faces_use_the_order_of_their_target_set() {
+        // An abstraction of squaring on intervals is sound if the abstracted square is included in the abstract square
+        let includes = |(a, b): &(i32, i32), (c, d): &(i32, i32)| c <= a && b <= d;
+        let diagram = |square: fn(&(i32, i32)) -> (i32, i32)| {
+            Diagram::new(
+                vec![
+                    Set::new((-5..5).collect::<Vec<i32>>()),
+                    Set::<i32, _, _>::new_no_generating_set(),
+                    Set::<(i32, i32), _, _>::new_no_generating_set(),
+                    Set::new_no_generating_set().with_order(includes),
+                ],
+                vec![
+                    Map::new(0, 1, |x: &i32| x * x, "square"),
+                    Map::new(1, 3, |x: &i32| (*x, *x), "alpha"),
+                    Map::new(0, 2, |x: &i32| (*x, *x), "alpha"),
+                    Map::new(2, 3, square, "square#"),
+                ],
+            )
+            .with_lax_face(&[0, 1], &[2, 3])
+            .unwrap()
+        };
+
+        let sound = |(lo, hi): &(i32, i32)| (0, (lo * lo).max(hi * hi));
+        assert!(matches!(
+            diagram_commutes(&diagram(sound)).unwrap(),
+            CommutativeDiagramResult::Commutes { .. }
+        ));
+
+        let unsound = |(_lo, hi): &(i32, i32)| (0, hi.max(&0) * hi.max(&0));
+        match diagram_commutes(&diagram(unsound)).unwrap() {
+            CommutativeDiagramResult::Commutes { .. }
+            | CommutativeDiagramResult::Incomplete { .. } => {
+                panic!("Negative squares are lost")
+            }
+            CommutativeDiagramResult::DoesNotCommute(counterexamples) => {
+                assert!(counterexamples[0].to_string().starts_with(
+                    "square -> alpha is not below alpha -> square# from 0 to 3 on -5"
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn test_paths_through_lax_faces_are_not_compared() {
+        // Going through B stays below going through C, and m leaves the target of that lax square
+        let diagram = |k: fn(&i32) -> i32| {
+            Diagram::new(
+                vec![
+                    Set::new((0..5).collect::<Vec<i32>>()),
+                    Set::<i32, _, _>::new_no_generating_set(),
+                    Set::<i32, _, _>::new_no_generating_set(),
+                    Set::<i32, _, _>::new_no_generating_set_partially_ordered(),
+                    Set::<i32, _, _>::new_no_generating_set(),
+                ],
+                vec![
+                    Map::new(0, 1, |x: &i32| *x, "f"),
+                    Map::new(1, 3, |x: &i32| *x, "g"),
+                    Map::new(0, 2, |x: &i32| *x, "h"),
+                    Map::new(2, 3, k, "k"),
+                    Map::new(3, 4, |x: &i32| x * 2, "m"),
+                ],
+            )
+            .with_named_lax_face(&["f", "g"], &["h", "k"])
+            .unwrap()
+        };
+
+        match diagram_commutes(&diagram(|x| x + 1)).unwrap() {
+            CommutativeDiagramResult::Commutes { .. } => {}
+            CommutativeDiagramResult::DoesNotCommute(counterexamples) => {
+                panic!("{}", counterexamples[0])
+            }
+            CommutativeDiagramResult::Incomplete { stopped, .. } => {
+                panic!("Stopped early: {:?}", stopped)
+            }
+        }
+
+        // The face itself is still checked
+        match diagram_commutes(&diagram(|x| x - 1)).unwrap() {
+            CommutativeDiagramResult::Commutes { .. }
+            | CommutativeDiagramResult::Incomplete { .. } => {
+                panic!("Going through C ends up lower")
+            }
+            CommutativeDiagramResult::DoesNotCommute(counterexamples) => {
+                assert!(counterexamples[0]
+                    .to_string()
+                    .starts_with("f -> g is not below h -> k from 0 to 3 on 0"));
+            }
+        }
+    }
+
+    #[test]
+    fn test_lax_faces_into_partially_ordered_sets() {
+        let diagram = |elements: Vec<f64>| {
+            Diagram::new(
+                vec![Set::new(elements), Set::new_partially_ordered(vec![0.5])],
+                vec![
+                    Map::new(0, 1, |x: &f64| x.floor(), "floor"),
+                    Map::new(0, 1, |x: &f64| *x, "id"),
+                ],
+            )
+            .with_lax_face(&[0], &[1])
+            .unwrap()
+        };
+
+        assert!(matches!(
+            diagram_commutes(&diagram(vec![-1.5, 0.0, 2.25])).unwrap(),
+            CommutativeDiagramResult::Commutes { .. }
+        ));
+
+        // NaN is not comparable to anything, not even to itself
+        match diagram_commutes(&diagram(vec![-1.5, f64::NAN])).unwrap() {
+            CommutativeDiagramResult::Commutes { .. }
+            | CommutativeDiagramResult::Incomplete { .. } => {
+                panic!("NaN is not below NaN")
+            }
+            CommutativeDiagramResult::DoesNotCommute(counterexamples) => {
+                assert_eq!(counterexamples[0].element_ix, 1);
+                assert_eq!(counterexamples[0].comparison, Comparison::Below);
+            }
+        }
+    }
+
+    #[test]
+    fn test_float_addition_is_associative_up_to_rounding() {
+        let diagram = |sums: Rc<dyn SetLike>| {
+            Diagram::new(
+                vec![
+                    Set::new(vec![(0.1, 0.2, 0.3), (1e10, 1e-6, 3.0)]),
+                    Set::<(f64, f64), _, _>::new_no_generating_set(),
+                    Set::<(f64, f64), _, _>::new_no_generating_set(),
+                    sums,
+                ],
+                vec![
+                    Map::new(0, 1, |(a, b, c): &(f64, f64, f64)| (a + b, *c), "(+,id)"),
+                    Map::new(0, 2, |(a, b, c): &(f64, f64, f64)| (*a, b + c), "(id,+)"),
+                    Map::new(1, 3, |(a, b): &(f64, f64)| a + b, "(+)"),
+                    Map::new(2, 3, |(a, b): &(f64, f64)| a + b, "(+)"),
+                ],
+            )
+        };
+
+        // (0.1 + 0.2) + 0.3 rounds differently from 0.1 + (0.2 + 0.3)
+        assert!(matches!(
+            diagram_commutes(&diagram(Set::<f64, _, _>::new_no_generating_set())).unwrap(),
+            CommutativeDiagramResult::DoesNotCommute(_)
+        ));
+
+        // Approximate equality combines with other options, e.g. a property on the sums
+        let approximately = Set::new_no_generating_set_checked(|x: &f64| x.is_finite())
+            .with_equivalence(|a: &f64, b: &f64| (a - b).abs() <= 1e-12 * a.abs().max(b.abs()));
+        assert!(matches!(
+            diagram_commutes(&diagram(approximately)).unwrap(),
+            CommutativeDiagramResult::Commutes { .. }
+        ));
+    }
+
+    #[test]
+    fn test_quotient_sets_compare_normal_forms() {
+        fn reduce((n, d): &(i32, i32)) -> (i32, i32) {
+            let (mut a, mut b) = (n.abs(), d.abs());
+            while b != 0 {
+                (a, b) = (b, a % b);
+            }
+            (n / a, d / a)
+        }
+
+        // Doubling the numerator and denominator gives the same fraction, adding half to both does not
+        let diagram = |other: fn(&i32) -> (i32, i32)| {
+            Diagram::new(
+                vec![
+                    Set::new((1..10).collect::<Vec<i32>>()),
+                    Set::<(i32, i32), _, _>::new_no_generating_set().with_normal_form(reduce),
+                ],
+                vec![
+                    Map::new(0, 1, |x: &i32| (*x, 1), "as_fraction"),
+                    Map::new(0, 1, other, "other"),
+                ],
+            )
+        };
+
+        assert!(matches!(
+            diagram_commutes(&diagram(|x| (2 * x, 2))).unwrap(),
+            CommutativeDiagramResult::Commutes { .. }
+        ));
+
+        match diagram_commutes(&diagram(|x| (2 * x + 1, 3))).unwrap() {
+            CommutativeDiagramResult::Commutes { .. }
+            | CommutativeDiagramResult::Incomplete { .. } => {
+                panic!("(2x + 1) / 3 is not x")
+            }
+            CommutativeDiagramResult::DoesNotCommute(counterexamples) => {
+                // 1 = 3 / 3, so the first disagreement is on 2, where 5 / 3 can't be reduced
+                let counterexample = &counterexamples[0];
+                assert_eq!(
+                    counterexample.element.as_any().downcast_ref::<i32>(),
+                    Some(&2)
+                );
+                assert_eq!(
+                    counterexample
+                        .left_result
+                        .as_any()
+                        .downcast_ref::<(i32, i32)>(),
+                    Some(&(2, 1))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_normal_forms_replace_equivalences_and_orders() {
+        // The normal forms are byte strings rather than strings, so they can't be compared with the equivalence or the order
+        let diagram = |other: fn(&String) -> String| {
+            Diagram::new(
+                vec![
+                    Set::new(vec!["a".to_owned(), "B".to_owned()]),
+                    Set::<String, _, _>::new_no_generating_set()
+                        .with_equivalence(|a: &String, b: &String| a.eq_ignore_ascii_case(b))
+                        .with_order(|a: &String, b: &String| a <= b)
+                        .with_normal_form(|x: &String| x.to_lowercase().into_bytes()),
+                ],
+                vec![
+                    Map::new(0, 1, |x: &String| x.clone(), "id"),
+                    Map::new(0, 1, other, "other"),
+                ],
+            )
+        };
+
+        assert!(matches!(
+            diagram_commutes(&diagram(|x| x.to_uppercase())).unwrap(),
+            CommutativeDiagramResult::Commutes { .. }
+        ));
+        assert!(matches!(
+            diagram_commutes(
+                &diagram(|x| x.to_uppercase())
+                    .with_lax_face(&[0], &[1])
+                    .unwrap()
+            )
+            .unwrap(),
+            CommutativeDiagramResult::Commutes { .. }
+        ));
+
+        match diagram_commutes(&diagram(|x| x.repeat(2))).unwrap() {
+            CommutativeDiagramResult::Commutes { .. }
+            | CommutativeDiagramResult::Incomplete { .. } => panic!("aa is not a"),
+            CommutativeDiagramResult::DoesNotCommute(counterexamples) => {
+                assert_eq!(
+                    counterexamples[0]
+                        .right_result
+                        .as_any()
+                        .downcast_ref::<Vec<u8>>(),
+                    Some(&b"aa".to_vec())
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_observers_follow_and_cancel_checks() {
+        #[derive(Default)]
+        struct Counter {
+            paths: usize,
+            faces: usize,
+            elements: usize,
+            counterexamples: usize,
+            cancel_after: usize,
+        }
+
+        impl CheckObserver for Counter {
+            fn path_started(&mut self, _path: &[usize], _references: usize) {
+                self.paths += 1;
+            }
+
+            fn face_started(&mut self, _left: &[usize], _right: &[usize]) {
+                self.faces += 1;
+            }
+
+            fn element_checked(&mut self, _element: &Rc<dyn Element>, _checked_elements: &[usize]) {
+                self.elements += 1;
+            }
+
+            fn counterexample_found(&mut self, _counterexample: &Counterexample) {
+                self.counterexamples += 1;
+            }
+
+            fn should_cancel(&mut self) -> bool {
+                self.elements >= self.cancel_after
+            }
+        }
+
+        let diagram = || {
+            Diagram::new(
+                vec![
+                    Set::new((0..100).collect::<Vec<i32>>()),
+                    Set::<i32, _, _>::new_no_generating_set(),
+                ],
+                vec![
+                    Map::new(0, 1, |x: &i32| x + x, "double"),
+                    Map::new(0, 1, |x: &i32| 2 * x, "twice"),
+                    Map::new(0, 1, |x: &i32| x * x, "square"),
+                ],
+            )
+        };
+
+        // Doubling is the same both ways, so a check that stops early finds nothing, but must not pass either
+        let doubling = diagram().with_face(&[0], &[1]).unwrap();
+        let faces_only = CheckOptions {
+            faces_only: true,
+            ..CheckOptions::default()
+        };
+        let mut observer = Counter {
+            cancel_after: 50,
+            ..Default::default()
+        };
+        assert!(matches!(
+            diagram_commutes_observed(&doubling, &faces_only, &mut observer).unwrap(),
+            CommutativeDiagramResult::Incomplete {
+                stopped: Stopped::Cancelled,
+                ..
+            }
+        ));
+        assert_eq!(
+            (observer.paths, observer.faces, observer.elements),
+            (0, 1, 50)
+        );
+
+        for (options, stopped) in [
+            (
+                CheckOptions {
+                    element_budget: Some(20),
+                    ..faces_only.clone()
+                },
+                Stopped::ElementBudget,
+            ),
+            (
+                CheckOptions {
+                    time_budget: Some(Duration::ZERO),
+                    ..faces_only.clone()
+                },
+                Stopped::TimeBudget,
+            ),
+        ] {
+            match diagram_commutes_with(&doubling, &options).unwrap() {
+                CommutativeDiagramResult::Incomplete {
+                    stopped: reason, ..
+                } => assert_eq!(reason, stopped),
+                _ => panic!("The check should stop early"),
+            }
+        }
+
+        // Squaring only agrees with doubling on 0 and 2
+        let mut observer = Counter {
+            cancel_after: usize::MAX,
+            ..Default::default()
+        };
+        let all = CheckOptions {
+            max_counterexamples: None,
+            ..CheckOptions::default()
+        };
+        assert!(matches!(
+            diagram_commutes_observed(&diagram(), &all, &mut observer).unwrap(),
+            CommutativeDiagramResult::DoesNotCommute(_)
+        ));
+        assert_eq!(observer.paths, 3);
+        assert_eq!(observer.elements, 300);
+        assert_eq!(observer.counterexamples, 98);
+
+        // Declared faces are checked directly, even if their paths were compared with each other already
+        let doubling = Diagram::new(
+            vec![
+                Set::new((0..100).collect::<Vec<i32>>()),
+                Set::<i32, _, _>::new_no_generating_set(),
+            ],
+            vec![
+                Map::new(0, 1, |x: &i32| x + x, "double"),
+                Map::new(0, 1, |x: &i32| 2 * x, "twice"),
+            ],
+        )
+        .with_face(&[0], &[1])
+        .unwrap();
+        let mut observer = Counter {
+            cancel_after: usize::MAX,
+            ..Default::default()
+        };
+        assert!(matches!(
+            diagram_commutes_observed(&doubling, &CheckOptions::default(), &mut observer).unwrap(),
+            CommutativeDiagramResult::Commutes { .. }
+        ));
+        assert_eq!(
+            (observer.paths, observer.faces, observer.elements),
+            (2, 1, 300)
+        );
+    }
+
+    #[test]
+    fn test_only_declared_faces_are_checked() {
+        // A commuting square, a map that doesn't commute with it and a loop that makes the whole diagram cyclic
+        let diagram = Diagram::new(
+            vec![
+                Set::new((0..10).collect::<Vec<i32>>()),
+                Set::<i32, _, _>::new_no_generating_set(),
+                Set::<i32, _, _>::new_no_generating_set(),
+                Set::<i32, _, _>::new_no_generating_set(),
+            ],
+            vec![
+                Map::new(0, 1, |x: &i32| x + 1, "f"),
+                Map::new(1, 3, |x: &i32| 2 * x, "g"),
+                Map::new(0, 2, |x: &i32| 2 * x, "h"),
+                Map::new(2, 3, |x: &i32| x + 2, "k"),
+                Map::new(0, 3, |x: &i32| *x, "id"),
+                Map::new(3, 3, |x: &i32| x + 1, "succ"),
+            ],
+        );
+
+        assert!(diagram_commutes(&diagram).is_err());
+
+        let square = diagram.with_named_face(&["f", "g"], &["h", "k"]).unwrap();
+        assert!(matches!(
+            diagram_faces_commute(&square).unwrap(),
+            CommutativeDiagramResult::Commutes { .. }
+        ));
+
+        match diagram_faces_commute(&square.with_face(&[0, 1], &[4]).unwrap()).unwrap() {
+            CommutativeDiagramResult::Commutes { .. }
+            | CommutativeDiagramResult::Incomplete { .. } => {
+                panic!("2x + 2 is not x")
+            }
+            CommutativeDiagramResult::DoesNotCommute(counterexamples) => {
+                assert_eq!(counterexamples[0].left.maps, vec![0, 1]);
+                assert_eq!(counterexamples[0].right.maps, vec![4]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_faces_beyond_the_maximum_path_length_are_checked() {
+        // The loop on C makes the diagram cyclic, and g . f is too long to be enumerated with paths of a single map
+        let diagram = Diagram::new(
+            vec![
+                Set::new((0..10).collect::<Vec<i32>>()),
+                Set::<i32, _, _>::new_no_generating_set(),
+                Set::<i32, _, _>::new_no_generating_set(),
+            ],
+            vec![
+                Map::new(0, 1, |x: &i32| x + 1, "f"),
+                Map::new(1, 2, |x: &i32| 2 * x, "g"),
+                Map::new(0, 2, |x: &i32| 2 * x + 3, "h"),
+                Map::new(2, 2, |x: &i32| *x, "id"),
+            ],
+        );
+
+        assert!(matches!(
+            diagram_commutes_bounded(&diagram, 1).unwrap(),
+            CommutativeDiagramResult::Commutes { .. }
+        ));
+
+        let face = diagram.with_face(&[0, 1], &[2]).unwrap();
+        match diagram_commutes_bounded(&face, 1).unwrap() {
+            CommutativeDiagramResult::Commutes { .. }
+            | CommutativeDiagramResult::Incomplete { .. } => {
+                panic!("2x + 2 is not 2x + 3")
+            }
+            CommutativeDiagramResult::DoesNotCommute(counterexamples) => {
+                assert_eq!(counterexamples[0].left.maps, vec![0, 1]);
+                assert_eq!(counterexamples[0].right.maps, vec![2]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_named_faces_refer_to_single_maps() {
+        let diagram = || {
+            Diagram::new(
+                vec![
+                    Set::new(vec![1, 2, 3]),
+                    Set::<i32, _, _>::new_no_generating_set(),
+                ],
+                vec![
+                    Map::new(0, 1, |x: &i32| x + x, "double"),
+                    Map::new(0, 1, |x: &i32| 2 * x, "twice"),
+                    Map::new(0, 1, |x: &i32| x * 2, "twice"),
+                ],
+            )
+        };
+
+        assert_eq!(
+            diagram().with_named_face(&["double"], &["half"]).err(),
+            Some(DiagramError::UnknownMap("half".to_owned()))
+        );
+        assert_eq!(
+            diagram().with_named_face(&["double"], &["twice"]).err(),
+            Some(DiagramError::AmbiguousMap("twice".to_owned()))
+        );
+    }
+}
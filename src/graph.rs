@@ -1,5 +1,5 @@
 //! Provides graph logic, used for building diagrams
-//! Note that currently, only non-cyclic graphs are supported
+//! Cyclic graphs are only supported through bounded path enumeration, see `all_paths_bounded`
 
 pub trait Edge {
     type Node: PartialEq + Clone;
@@ -14,6 +14,22 @@ pub trait DiGraph {
 
     fn nodes(&self) -> Box<dyn Iterator<Item = Self::Node>>;
     fn outbounds(&self, node: &Self::Node) -> Box<dyn Iterator<Item = Self::Edge>>;
+
+    // Lazily enumerates all paths through this graph, failing on the first cycle
+    fn paths(&self) -> Paths<'_, Self>
+    where
+        Self: Sized,
+    {
+        Paths::new(self, None)
+    }
+
+    // Lazily enumerates all paths of at most `max_length` edges, which also terminates on cyclic graphs
+    fn paths_bounded(&self, max_length: usize) -> Paths<'_, Self>
+    where
+        Self: Sized,
+    {
+        Paths::new(self, Some(max_length))
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -22,7 +38,7 @@ impl std::fmt::Display for CyclicGraphError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(
             f,
-            "Graph contains at least one cycle - this is currently unsupported"
+            "Graph contains at least one cycle - use a bounded path search for cyclic graphs"
         )
     }
 }
@@ -32,50 +48,106 @@ pub fn all_paths<G>(graph: &G) -> Result<Vec<Vec<G::Edge>>, CyclicGraphError>
 where
     G: DiGraph,
 {
-    // Stores a list of all found paths
-    let mut paths = Vec::new();
-
-    fn search<G>(
-        graph: &G,
-        current_path: Vec<G::Edge>,
-        paths: &mut Vec<Vec<G::Edge>>,
-        initial_vertex: &G::Node,
-    ) -> Result<(), CyclicGraphError>
-    where
-        G: DiGraph,
-    {
-        let current_destination = current_path.last().unwrap().to().clone();
-        if current_destination == *initial_vertex {
-            return Err(CyclicGraphError);
-        }
+    graph.paths().collect()
+}
 
-        for next in graph.outbounds(&current_destination) {
-            // Compute the newly found path
-            let mut new_path = current_path.clone();
-            new_path.push(next);
+// Computes all paths in this graph of at most `max_length` edges.
+// Cycles are permitted here: a path keeps traversing a loop until it reaches the maximum length
+pub fn all_paths_bounded<G>(graph: &G, max_length: usize) -> Vec<Vec<G::Edge>>
+where
+    G: DiGraph,
+{
+    // A bounded search never reports a cycle
+    graph
+        .paths_bounded(max_length)
+        .map(Result::unwrap)
+        .collect()
+}
 
-            // And store it in the result set
-            paths.push(new_path.clone());
+/// Lazily enumerates the paths through a graph in depth-first order, starting from each vertex in turn.
+/// Only the path currently being explored is held in memory, so consumers can stop at any point.
+/// In unbounded mode, the iterator yields a single `CyclicGraphError` once it runs into a cycle and then stops.
+pub struct Paths<'a, G>
+where
+    G: DiGraph,
+{
+    graph: &'a G,
+    max_length: Option<usize>,
+    nodes: Box<dyn Iterator<Item = G::Node>>,
+    path: Vec<G::Edge>,
+    // The outbounds still to explore from the end of each prefix of the current path (the first from the initial vertex)
+    stack: Vec<Box<dyn Iterator<Item = G::Edge>>>,
+    done: bool,
+}
 
-            // Now continue the search from our new position
-            search(graph, new_path, paths, initial_vertex)?;
+impl<'a, G> Paths<'a, G>
+where
+    G: DiGraph,
+{
+    fn new(graph: &'a G, max_length: Option<usize>) -> Paths<'a, G> {
+        Paths {
+            graph,
+            max_length,
+            nodes: graph.nodes(),
+            path: Vec::new(),
+            stack: Vec::new(),
+            done: max_length == Some(0),
         }
-
-        Ok(())
     }
+}
 
-    // Initiate the search from each vertex
-    for initial_vertex in graph.nodes() {
-        // Initiate the search from this vertex through each outbound
-        for outbound in graph.outbounds(&initial_vertex) {
-            paths.push(vec![outbound.clone()]);
+impl<'a, G> Iterator for Paths<'a, G>
+where
+    G: DiGraph,
+{
+    type Item = Result<Vec<G::Edge>, CyclicGraphError>;
 
-            // The search will modify the list of discovered paths in-place
-            search(graph, vec![outbound], &mut paths, &initial_vertex)?;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
         }
-    }
 
-    Ok(paths)
+        loop {
+            // Initiate the search from the next vertex once the previous one is exhausted
+            let Some(outbounds) = self.stack.last_mut() else {
+                let initial_vertex = self.nodes.next()?;
+                self.stack.push(self.graph.outbounds(&initial_vertex));
+                continue;
+            };
+
+            let Some(next) = outbounds.next() else {
+                // Nothing left to explore here, step back
+                self.stack.pop();
+                self.path.pop();
+                continue;
+            };
+
+            // An unbounded search would never terminate if we revisit a vertex on the current path
+            if self.max_length.is_none()
+                && (*next.from() == *next.to()
+                    || self.path.iter().any(|edge| edge.from() == next.to()))
+            {
+                self.done = true;
+                return Some(Err(CyclicGraphError));
+            }
+
+            self.path.push(next);
+            let found = self.path.clone();
+
+            // Continue the search from our new position, unless the path is already as long as allowed
+            if self
+                .max_length
+                .is_some_and(|max_length| self.path.len() >= max_length)
+            {
+                self.path.pop();
+            } else {
+                let destination = self.path.last().unwrap().to().clone();
+                self.stack.push(self.graph.outbounds(&destination));
+            }
+
+            return Some(Ok(found));
+        }
+    }
 }
 
 #[cfg(test)]
@@ -169,4 +241,66 @@ mod tests {
         ]));
         assert!(paths.contains(&vec![TestGraphEdge { from: 2, to: 4 },]));
     }
+
+    #[test]
+    fn test_bounded_paths_on_cyclic_graph() {
+        let graph = TestGraph {
+            nodes: vec![1, 2, 3],
+            edges: vec![
+                TestGraphEdge { from: 1, to: 2 },
+                TestGraphEdge { from: 2, to: 3 },
+                TestGraphEdge { from: 3, to: 2 },
+            ],
+        };
+
+        // The cycle between 2 and 3 is not reachable through 1 again, but must still be detected
+        assert!(all_paths(&graph).is_err());
+
+        let paths = all_paths_bounded(&graph, 3);
+        assert!(paths.iter().all(|path| !path.is_empty() && path.len() <= 3));
+        assert!(paths.contains(&vec![
+            TestGraphEdge { from: 1, to: 2 },
+            TestGraphEdge { from: 2, to: 3 },
+            TestGraphEdge { from: 3, to: 2 },
+        ]));
+        assert!(paths.contains(&vec![
+            TestGraphEdge { from: 2, to: 3 },
+            TestGraphEdge { from: 3, to: 2 },
+            TestGraphEdge { from: 2, to: 3 },
+        ]));
+
+        // 1 starts 3 paths (of length 1, 2, 3), 2 and 3 each start 3 paths along the loop
+        assert_eq!(paths.len(), 9);
+    }
+
+    #[test]
+    fn test_paths_are_enumerated_lazily() {
+        let graph = TestGraph {
+            nodes: vec![1, 2, 3],
+            edges: vec![
+                TestGraphEdge { from: 1, to: 2 },
+                TestGraphEdge { from: 2, to: 3 },
+                TestGraphEdge { from: 3, to: 1 },
+            ],
+        };
+
+        // The first paths are available before the cycle is discovered
+        let mut paths = graph.paths();
+        assert_eq!(
+            paths.next().unwrap().unwrap(),
+            vec![TestGraphEdge { from: 1, to: 2 }]
+        );
+        assert_eq!(
+            paths.next().unwrap().unwrap(),
+            vec![
+                TestGraphEdge { from: 1, to: 2 },
+                TestGraphEdge { from: 2, to: 3 },
+            ]
+        );
+        assert!(paths.next().unwrap().is_err());
+        assert!(paths.next().is_none());
+
+        // The bounded enumeration goes round the cycle up to the given length from each vertex
+        assert_eq!(graph.paths_bounded(100).count(), 300);
+    }
 }
@@ -1,5 +1,10 @@
+pub mod builder;
 pub mod diagram;
 pub mod graph;
+pub mod render;
+#[cfg(feature = "proptest")]
+pub mod strategy;
+pub mod sync;
 
 pub fn add(left: usize, right: usize) -> usize {
     left + right
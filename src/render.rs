@@ -0,0 +1,505 @@
+//! Rendering of diagrams, e.g. to attach pictures of failing diagrams to CI runs, include them in papers or read them in test logs
+//!
+//! Sets are described by their name (see `Diagram::with_set_names`), maps by their name.
+//! Maps along the paths of counterexamples are highlighted.
+
+use crate::diagram::{CommutativeDiagramResult, Comparison, Counterexample, Diagram, PathTrace};
+use std::collections::{HashMap, HashSet};
+
+impl Diagram {
+    /// Renders the diagram as a Graphviz DOT graph, highlighting the failing paths if the result does not commute
+    pub fn to_dot(&self, result: Option<&CommutativeDiagramResult>) -> String {
+        let failing = failing_maps(result);
+
+        let mut dot = String::from("digraph {\n");
+        for (ix, set) in self.sets.iter().enumerate() {
+            dot.push_str(&format!(
+                "    s{} [label=\"{} ({} elements)\"];\n",
+                ix,
+                escape_dot(self.set_name(ix)),
+                set.elements().count()
+            ));
+        }
+
+        for (ix, map) in self.maps.iter().enumerate() {
+            let style = if failing.contains(&ix) {
+                ", color=red, fontcolor=red, penwidth=2"
+            } else {
+                ""
+            };
+
+            dot.push_str(&format!(
+                "    s{} -> s{} [label=\"{}\"{}];\n",
+                map.from,
+                map.to,
+                escape_dot(&map.name),
+                style
+            ));
+        }
+        dot.push('}');
+        dot.push('\n');
+
+        dot
+    }
+}
+
+impl Diagram {
+    /// Renders the diagram as a tikz-cd environment, highlighting the failing paths if the result does not commute.
+    /// Sets are placed at their position hint (see `Diagram::with_set_position`), others are laid out left to right along the maps.
+    /// When several sets are hinted at the same cell, the first one gets it and the others are moved down its column.
+    /// Set and map names are inserted verbatim, so they may contain LaTeX.
+    pub fn to_tikz_cd(&self, result: Option<&CommutativeDiagramResult>) -> String {
+        let failing = failing_maps(result);
+        let positions = self.grid_positions();
+
+        let rows = positions.iter().map(|(row, _)| row + 1).max().unwrap_or(0);
+        let columns = positions
+            .iter()
+            .map(|(_, column)| column + 1)
+            .max()
+            .unwrap_or(0);
+        let mut cells = vec![vec![String::new(); columns]; rows];
+
+        for (ix, (row, column)) in positions.iter().enumerate() {
+            cells[*row][*column] = self.set_name(ix).to_owned();
+        }
+
+        // Parallel maps between the same sets are bent apart
+        let mut seen_between: HashMap<(usize, usize), usize> = HashMap::new();
+
+        for (ix, map) in self.maps.iter().enumerate() {
+            let (from_row, from_column) = positions[map.from];
+            let (to_row, to_column) = positions[map.to];
+
+            let parallel = seen_between.entry((map.from, map.to)).or_default();
+            let mut options = if map.from == map.to {
+                vec![format!(
+                    "loop, distance=2em, in={}, out={}",
+                    55 + 45 * *parallel,
+                    125 + 45 * *parallel
+                )]
+            } else {
+                let direction = "d".repeat(to_row.saturating_sub(from_row))
+                    + &"u".repeat(from_row.saturating_sub(to_row))
+                    + &"r".repeat(to_column.saturating_sub(from_column))
+                    + &"l".repeat(from_column.saturating_sub(to_column));
+                let mut options = vec![direction];
+                if *parallel > 0 {
+                    options.push(format!("bend left={}", 20 * *parallel));
+                }
+                options
+            };
+            *parallel += 1;
+
+            options.push(format!("\"{{{}}}\"", map.name));
+            if failing.contains(&ix) {
+                options.push("red".to_owned());
+            }
+
+            cells[from_row][from_column].push_str(&format!(" \\arrow[{}]", options.join(", ")));
+        }
+
+        let mut tikz = String::from("\\begin{tikzcd}\n");
+        let lines: Vec<String> = cells.iter().map(|row| row.join(" & ")).collect();
+        tikz.push_str(&lines.join(" \\\\\n"));
+        tikz.push_str("\n\\end{tikzcd}\n");
+
+        tikz
+    }
+
+    /// Renders a LaTeX report of the counterexamples in a non-commuting result, including the diagram with the failing paths highlighted.
+    /// Returns None if the diagram commutes.
+    pub fn latex_failure_report(&self, result: &CommutativeDiagramResult) -> Option<String> {
+        let CommutativeDiagramResult::DoesNotCommute(counterexamples) = result else {
+            return None;
+        };
+
+        let mut report = String::from("\\[\n");
+        report.push_str(&self.to_tikz_cd(Some(result)));
+        report.push_str("\\]\n\\begin{enumerate}\n");
+        for counterexample in counterexamples {
+            report.push_str(&format!(
+                "  \\item {}\n",
+                latex_counterexample(counterexample)
+            ));
+        }
+        report.push_str("\\end{enumerate}\n");
+
+        Some(report)
+    }
+
+    // Positions all sets on a grid: sets with a position hint go there, others are put in the column of their longest incoming path
+    fn grid_positions(&self) -> Vec<(usize, usize)> {
+        let set_count = self.sets.len();
+
+        // Longest path lengths, capped by the number of sets so that cycles don't keep pushing sets to the right
+        let mut depths = vec![0; set_count];
+        for _ in 0..set_count {
+            for map in self.maps.iter().filter(|map| map.from != map.to) {
+                depths[map.to] = depths[map.to].max((depths[map.from] + 1).min(set_count - 1));
+            }
+        }
+
+        let mut occupied: HashSet<(usize, usize)> =
+            self.set_positions.iter().flatten().cloned().collect();
+        let mut taken_hints = HashSet::new();
+
+        (0..set_count)
+            .map(|ix| match self.set_positions[ix] {
+                // The first set hinted at a cell gets it
+                Some(position) if taken_hints.insert(position) => position,
+                // Others go to the first free cell below their hint, or in the column of their depth
+                hint => {
+                    let (first_row, column) = hint.unwrap_or((0, depths[ix]));
+                    let position = (first_row..)
+                        .map(|row| (row, column))
+                        .find(|position| !occupied.contains(position))
+                        .unwrap();
+                    occupied.insert(position);
+                    position
+                }
+            })
+            .collect()
+    }
+}
+
+impl Diagram {
+    /// Renders the diagram as a Mermaid flowchart, highlighting the failing paths if the result does not commute
+    pub fn to_mermaid(&self, result: Option<&CommutativeDiagramResult>) -> String {
+        let failing = failing_maps(result);
+
+        let mut mermaid = String::from("flowchart LR\n");
+        for (ix, set) in self.sets.iter().enumerate() {
+            mermaid.push_str(&format!(
+                "    s{}[\"{} ({} elements)\"]\n",
+                ix,
+                escape_mermaid(self.set_name(ix)),
+                set.elements().count()
+            ));
+        }
+
+        for map in self.maps.iter() {
+            mermaid.push_str(&format!(
+                "    s{} -->|\"{}\"| s{}\n",
+                map.from,
+                escape_mermaid(&map.name),
+                map.to
+            ));
+        }
+
+        // Links are styled by the order in which they were declared, which is the order of the maps
+        if !failing.is_empty() {
+            let mut failing: Vec<usize> = failing.into_iter().collect();
+            failing.sort();
+
+            mermaid.push_str(&format!(
+                "    linkStyle {} stroke:red,stroke-width:2px\n",
+                failing
+                    .iter()
+                    .map(|ix| ix.to_string())
+                    .collect::<Vec<String>>()
+                    .join(",")
+            ));
+        }
+
+        mermaid
+    }
+
+    /// Renders the diagram as plain text, e.g. for test logs. Maps along failing paths are marked with a `!`,
+    /// and each counterexample is listed with the sets it passes through.
+    pub fn to_text(&self, result: Option<&CommutativeDiagramResult>) -> String {
+        let failing = failing_maps(result);
+
+        let mut text = String::from("Sets:\n");
+        for (ix, set) in self.sets.iter().enumerate() {
+            text.push_str(&format!(
+                "    {} ({} elements)\n",
+                self.set_name(ix),
+                set.elements().count()
+            ));
+        }
+
+        text.push_str("Maps:\n");
+        for (ix, map) in self.maps.iter().enumerate() {
+            text.push_str(&format!(
+                "  {} {} --{}--> {}\n",
+                if failing.contains(&ix) { "!" } else { " " },
+                self.set_name(map.from),
+                map.name,
+                self.set_name(map.to)
+            ));
+        }
+
+        if let Some(CommutativeDiagramResult::DoesNotCommute(counterexamples)) = result {
+            text.push_str("Counterexamples:\n");
+            for counterexample in counterexamples {
+                text.push_str(&format!(
+                    "    on {}:\n      left:  {} gets {}\n      right: {} gets {}\n",
+                    counterexample.element.name(),
+                    self.describe_path(&counterexample.left),
+                    counterexample.left_result.name(),
+                    self.describe_path(&counterexample.right),
+                    counterexample.right_result.name()
+                ));
+            }
+        }
+
+        text
+    }
+
+    // Describes a path by the sets it passes through, e.g. A --f--> B --g--> C
+    fn describe_path(&self, path: &PathTrace) -> String {
+        let mut description = self.set_name(self.maps[path.maps[0]].from).to_owned();
+        for ix in path.maps.iter() {
+            let map = &self.maps[*ix];
+            description.push_str(&format!(" --{}--> {}", map.name, self.set_name(map.to)));
+        }
+        description
+    }
+}
+
+fn latex_counterexample(counterexample: &Counterexample) -> String {
+    let relation = match counterexample.comparison {
+        Comparison::Equal => "and ${}$ disagree",
+        Comparison::Below => "is not below ${}$",
+    }
+    .replace("{}", &latex_composition(&counterexample.right));
+    format!(
+        "${}$ {} on \\texttt{{{}}}: the left path gives \\texttt{{{}}} while the right path gives \\texttt{{{}}}.",
+        latex_composition(&counterexample.left),
+        relation,
+        escape_latex(&counterexample.element.name()),
+        escape_latex(&counterexample.left_result.name()),
+        escape_latex(&counterexample.right_result.name())
+    )
+}
+
+// Paths are applied left to right, so their composition is written right to left
+fn latex_composition(path: &PathTrace) -> String {
+    path.map_names
+        .iter()
+        .rev()
+        .cloned()
+        .collect::<Vec<String>>()
+        .join(" \\circ ")
+}
+
+fn escape_mermaid(label: &str) -> String {
+    label.replace('"', "#quot;")
+}
+
+fn escape_latex(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '\\' => "\\textbackslash{}".to_owned(),
+            '~' => "\\textasciitilde{}".to_owned(),
+            '^' => "\\textasciicircum{}".to_owned(),
+            '#' | '$' | '%' | '&' | '_' | '{' | '}' => format!("\\{}", c),
+            '\n' => " ".to_owned(),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+// The indices of the maps along either path of any counterexample
+fn failing_maps(result: Option<&CommutativeDiagramResult>) -> HashSet<usize> {
+    match result {
+        Some(CommutativeDiagramResult::DoesNotCommute(counterexamples)) => counterexamples
+            .iter()
+            .flat_map(|counterexample| {
+                counterexample
+                    .left
+                    .maps
+                    .iter()
+                    .chain(counterexample.right.maps.iter())
+                    .cloned()
+            })
+            .collect(),
+        _ => HashSet::new(),
+    }
+}
+
+fn escape_dot(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagram::{diagram_commutes, diagram_commutes_bounded, Map, Set};
+
+    #[test]
+    fn test_dot_highlights_failing_paths() {
+        let diagram = Diagram::new(
+            vec![
+                Set::new((-5..5).collect::<Vec<i32>>()),
+                Set::<i32, _, _>::new_no_generating_set(),
+                Set::<i32, _, _>::new_no_generating_set(),
+            ],
+            vec![
+                Map::new(0, 1, |x: &i32| *x, "id"),
+                Map::new(0, 1, |x: &i32| x.abs(), "abs"),
+                Map::new(1, 2, |x: &i32| x * 2, "\"double\""),
+            ],
+        )
+        .with_set_names(&["Z", "Z'"]);
+
+        let result = diagram_commutes(&diagram).unwrap();
+        let dot = diagram.to_dot(Some(&result));
+
+        assert!(dot.starts_with("digraph {\n"));
+        assert!(dot.contains("    s0 [label=\"Z (10 elements)\"];\n"));
+        assert!(dot.contains("    s1 [label=\"Z' (0 elements)\"];\n"));
+        assert!(dot.contains("    s2 [label=\"2 (0 elements)\"];\n"));
+        assert!(
+            dot.contains("    s0 -> s1 [label=\"abs\", color=red, fontcolor=red, penwidth=2];\n")
+        );
+        assert!(dot.contains("    s1 -> s2 [label=\"\\\"double\\\"\"];\n"));
+
+        // Without a failing result, nothing is highlighted
+        assert!(!diagram.to_dot(None).contains("color=red"));
+    }
+
+    #[test]
+    fn test_tikz_cd_of_square() {
+        let diagram = Diagram::new(
+            vec![
+                Set::new((-5..5).collect::<Vec<i32>>()),
+                Set::<i32, _, _>::new_no_generating_set(),
+                Set::<i32, _, _>::new_no_generating_set(),
+                Set::<i32, _, _>::new_no_generating_set(),
+            ],
+            vec![
+                Map::new(0, 1, |x: &i32| *x, "f"),
+                Map::new(0, 2, |x: &i32| x.abs(), "g"),
+                Map::new(1, 3, |x: &i32| *x, "h"),
+                Map::new(2, 3, |x: &i32| *x, "k"),
+            ],
+        )
+        .with_set_names(&["A", "B", "C", "D"])
+        .with_set_position(0, (0, 0))
+        .with_set_position(1, (0, 1))
+        .with_set_position(2, (1, 0))
+        .with_set_position(3, (1, 1));
+
+        assert_eq!(
+            diagram.to_tikz_cd(None),
+            "\\begin{tikzcd}\n\
+             A \\arrow[r, \"{f}\"] \\arrow[d, \"{g}\"] & B \\arrow[d, \"{h}\"] \\\\\n\
+             C \\arrow[r, \"{k}\"] & D\n\
+             \\end{tikzcd}\n"
+        );
+
+        let result = diagram_commutes(&diagram).unwrap();
+        let report = diagram.latex_failure_report(&result).unwrap();
+        assert!(report.contains("\\arrow[d, \"{g}\", red]"));
+        assert!(report.contains(
+            "\\item $h \\circ f$ and $k \\circ g$ disagree on \\texttt{-5}: the left path gives \\texttt{-5} while the right path gives \\texttt{5}."
+        ));
+    }
+
+    #[test]
+    fn test_tikz_cd_layout_without_hints() {
+        let diagram = Diagram::new(
+            vec![
+                Set::new(vec![1]),
+                Set::<i32, _, _>::new_no_generating_set(),
+                Set::<i32, _, _>::new_no_generating_set(),
+            ],
+            vec![
+                Map::new(0, 1, |x: &i32| *x, "f"),
+                Map::new(1, 2, |x: &i32| *x, "g"),
+                Map::new(0, 2, |x: &i32| *x, "h"),
+                Map::new(1, 1, |x: &i32| *x, "e"),
+            ],
+        );
+
+        // Sets are laid out along their longest incoming path
+        assert_eq!(
+            diagram.to_tikz_cd(None),
+            "\\begin{tikzcd}\n\
+             0 \\arrow[r, \"{f}\"] \\arrow[rr, \"{h}\"] & 1 \\arrow[r, \"{g}\"] \\arrow[loop, distance=2em, in=55, out=125, \"{e}\"] & 2\n\
+             \\end{tikzcd}\n"
+        );
+
+        assert!(diagram
+            .latex_failure_report(&diagram_commutes_bounded(&diagram, 3).unwrap())
+            .is_none());
+    }
+
+    #[test]
+    fn test_tikz_cd_colliding_and_invalid_hints() {
+        // C is hinted at B's cell, so it is moved below it, and the hint for a fourth set is ignored
+        let diagram = Diagram::new(
+            vec![
+                Set::new(vec![1]),
+                Set::<i32, _, _>::new_no_generating_set(),
+                Set::<i32, _, _>::new_no_generating_set(),
+            ],
+            vec![
+                Map::new(0, 1, |x: &i32| *x, "f"),
+                Map::new(0, 2, |x: &i32| *x, "g"),
+            ],
+        )
+        .with_set_names(&["A", "B", "C"])
+        .with_set_position(0, (0, 0))
+        .with_set_position(1, (0, 1))
+        .with_set_position(2, (0, 1))
+        .with_set_position(3, (2, 2));
+
+        assert_eq!(
+            diagram.to_tikz_cd(None),
+            "\\begin{tikzcd}\n\
+             A \\arrow[r, \"{f}\"] \\arrow[dr, \"{g}\"] & B \\\\\n \
+             & C\n\
+             \\end{tikzcd}\n"
+        );
+    }
+
+    #[test]
+    fn test_mermaid_and_text_mark_failing_paths() {
+        let diagram = Diagram::new(
+            vec![
+                Set::new((-5..5).collect::<Vec<i32>>()),
+                Set::<i32, _, _>::new_no_generating_set(),
+                Set::<i32, _, _>::new_no_generating_set(),
+            ],
+            vec![
+                Map::new(0, 1, |x: &i32| x * 2, "double"),
+                Map::new(0, 2, |x: &i32| *x, "id"),
+                Map::new(0, 2, |x: &i32| x.abs(), "abs"),
+            ],
+        )
+        .with_set_names(&["A", "B", "C"]);
+
+        let result = diagram_commutes(&diagram).unwrap();
+
+        assert_eq!(
+            diagram.to_mermaid(Some(&result)),
+            "flowchart LR\n\
+             \x20   s0[\"A (10 elements)\"]\n\
+             \x20   s1[\"B (0 elements)\"]\n\
+             \x20   s2[\"C (0 elements)\"]\n\
+             \x20   s0 -->|\"double\"| s1\n\
+             \x20   s0 -->|\"id\"| s2\n\
+             \x20   s0 -->|\"abs\"| s2\n\
+             \x20   linkStyle 1,2 stroke:red,stroke-width:2px\n"
+        );
+
+        assert_eq!(
+            diagram.to_text(Some(&result)),
+            "Sets:\n\
+             \x20   A (10 elements)\n\
+             \x20   B (0 elements)\n\
+             \x20   C (0 elements)\n\
+             Maps:\n\
+             \x20   A --double--> B\n\
+             \x20 ! A --id--> C\n\
+             \x20 ! A --abs--> C\n\
+             Counterexamples:\n\
+             \x20   on -5:\n\
+             \x20     left:  A --id--> C gets -5\n\
+             \x20     right: A --abs--> C gets 5\n"
+        );
+    }
+}
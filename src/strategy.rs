@@ -0,0 +1,236 @@
+//! Generating sets drawn from proptest strategies (requires the `proptest` feature)
+//!
+//! Instead of building the generating elements by hand, a `StrategySet` draws them from a
+//! `proptest::strategy::Strategy`. Generation is seeded, so a run can be reproduced exactly,
+//! and counterexamples are shrunk through proptest's value trees.
+//!
+//! ```
+//! use commuter::diagram::{Diagram, Set, Map, diagram_commutes, CommutativeDiagramResult};
+//! use commuter::strategy::StrategySet;
+//!
+//! let diagram = Diagram::new(
+//!     vec![
+//!         StrategySet::new((-1000..1000i32, -1000..1000i32), 256, 42),
+//!         Set::<i32, _, _>::new_no_generating_set(),
+//!     ],
+//!     vec![
+//!         Map::new(0, 1, |(a, b): &(i32, i32)| a + b, "a + b"),
+//!         Map::new(0, 1, |(a, b): &(i32, i32)| b + a, "b + a"),
+//!     ],
+//! );
+//!
+//! assert!(matches!(
+//!     diagram_commutes(&diagram).unwrap(),
+//!     CommutativeDiagramResult::Commutes { .. }
+//! ));
+//! ```
+
+use crate::builder::TypedSet;
+use crate::diagram::{Element, ElementType, SetLike};
+use proptest::strategy::{Strategy, ValueTree};
+use proptest::test_runner::{Config, RngAlgorithm, TestRng, TestRunner};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+type Tree<T> = Box<dyn ValueTree<Value = T>>;
+
+pub struct StrategySet<T>
+where
+    T: Clone + Element + Sized,
+{
+    elements: Vec<T>,
+    seed: u64,
+    // Recreates the value tree that generated the element at the given position
+    regenerate: Box<dyn Fn(usize) -> Tree<T>>,
+    // The tree of the counterexample currently being shrunk
+    shrinking: Rc<RefCell<Option<Tree<T>>>>,
+}
+
+impl<T> StrategySet<T>
+where
+    T: Clone + Element + Sized + std::fmt::Debug + 'static,
+{
+    // Draws `cases` generating elements from the strategy, using a deterministic RNG seeded with `seed`
+    pub fn new<S>(strategy: S, cases: usize, seed: u64) -> Rc<Self>
+    where
+        S: Strategy<Value = T> + 'static,
+        S::Tree: 'static,
+    {
+        let strategy = Rc::new(strategy);
+
+        let mut runner = seeded_runner(seed);
+        let elements = (0..cases)
+            .map(|_| new_tree(strategy.as_ref(), &mut runner).current())
+            .collect();
+
+        let regenerate = move |ix: usize| {
+            // Value trees are drawn in sequence from the same RNG, so all trees before it are drawn again
+            let mut runner = seeded_runner(seed);
+            for _ in 0..ix {
+                new_tree(strategy.as_ref(), &mut runner);
+            }
+            Box::new(new_tree(strategy.as_ref(), &mut runner)) as Tree<T>
+        };
+
+        Rc::new(Self {
+            elements,
+            seed,
+            regenerate: Box::new(regenerate),
+            shrinking: Rc::new(RefCell::new(None)),
+        })
+    }
+}
+
+impl<T> SetLike for StrategySet<T>
+where
+    T: Clone + Element + Sized + std::fmt::Debug + 'static,
+{
+    fn elements(&self) -> Box<dyn Iterator<Item = Rc<dyn Element>>> {
+        let owned_els = self.elements.clone();
+        Box::new(owned_els.into_iter().map(|e| Rc::new(e) as Rc<dyn Element>))
+    }
+
+    fn check(&self, _element: &Rc<dyn Element>) -> bool {
+        true
+    }
+
+    fn filter(&self, _element: &Rc<dyn Element>) -> bool {
+        true
+    }
+
+    fn seed(&self) -> Option<u64> {
+        Some(self.seed)
+    }
+
+    fn element_type(&self) -> Option<ElementType> {
+        Some(ElementType::of::<T>())
+    }
+
+    // Follows proptest's shrinking protocol: the first candidate simplifies the value tree.
+    // If a candidate is accepted, it is shrunk further from the same tree. If the next candidate is requested instead,
+    // the previous one passed, so the tree is complicated again.
+    fn shrink(&self, element: &Rc<dyn Element>) -> Box<dyn Iterator<Item = Rc<dyn Element>>> {
+        let mut shrinking = self.shrinking.borrow_mut();
+
+        let continues_shrinking = shrinking
+            .as_ref()
+            .is_some_and(|tree| tree.current().eq(element));
+        if !continues_shrinking {
+            // Start from the tree that generated this element, if it was generated here at all
+            let Some(ix) = self.elements.iter().position(|e| e.eq(element)) else {
+                return Box::new(std::iter::empty());
+            };
+            *shrinking = Some((self.regenerate)(ix));
+        }
+
+        Box::new(ShrinkCandidates {
+            tree: self.shrinking.clone(),
+            started: false,
+        })
+    }
+}
+
+impl<T> TypedSet for StrategySet<T>
+where
+    T: Clone + Element + Sized + PartialEq + std::fmt::Debug + 'static,
+{
+    type Element = T;
+}
+
+struct ShrinkCandidates<T> {
+    tree: Rc<RefCell<Option<Tree<T>>>>,
+    started: bool,
+}
+
+impl<T> Iterator for ShrinkCandidates<T>
+where
+    T: Clone + Element + Sized + std::fmt::Debug + 'static,
+{
+    type Item = Rc<dyn Element>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut tree = self.tree.borrow_mut();
+        let tree = tree.as_mut()?;
+
+        let moved = if self.started {
+            tree.complicate()
+        } else {
+            tree.simplify()
+        };
+        self.started = true;
+
+        if moved {
+            Some(Rc::new(tree.current()))
+        } else {
+            None
+        }
+    }
+}
+
+fn new_tree<S>(strategy: &S, runner: &mut TestRunner) -> S::Tree
+where
+    S: Strategy,
+{
+    strategy
+        .new_tree(runner)
+        .expect("Strategy failed to generate a value")
+}
+
+fn seeded_runner(seed: u64) -> TestRunner {
+    let mut bytes = [0u8; 32];
+    bytes[..8].copy_from_slice(&seed.to_le_bytes());
+
+    TestRunner::new_with_rng(
+        Config::default(),
+        TestRng::from_seed(RngAlgorithm::ChaCha, &bytes),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagram::{diagram_commutes, CommutativeDiagramResult, Diagram, Map, Set};
+
+    #[test]
+    fn test_same_seed_same_elements() {
+        let names = |set: Rc<StrategySet<i32>>| -> Vec<String> {
+            set.elements().map(|element| element.name()).collect()
+        };
+
+        assert_eq!(
+            names(StrategySet::new(0..1000i32, 50, 7)),
+            names(StrategySet::new(0..1000i32, 50, 7))
+        );
+        assert_ne!(
+            names(StrategySet::new(0..1000i32, 50, 7)),
+            names(StrategySet::new(0..1000i32, 50, 8))
+        );
+    }
+
+    #[test]
+    fn test_counterexample_shrunk_through_value_tree() {
+        let diagram = Diagram::new(
+            vec![
+                StrategySet::new(0..1000i32, 100, 1),
+                Set::<i32, _, _>::new_no_generating_set(),
+            ],
+            vec![
+                Map::new(0, 1, |x: &i32| *x, "id"),
+                Map::new(0, 1, |x: &i32| if *x < 37 { *x } else { 0 }, "truncate"),
+            ],
+        );
+
+        match diagram_commutes(&diagram).unwrap() {
+            CommutativeDiagramResult::Commutes { .. }
+            | CommutativeDiagramResult::Incomplete { .. } => {
+                panic!("truncate is not the identity")
+            }
+            CommutativeDiagramResult::DoesNotCommute(counterexamples) => {
+                assert_eq!(
+                    counterexamples[0].element.as_any().downcast_ref::<i32>(),
+                    Some(&37)
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,680 @@
+//! Sync - diagrams that can be shared between threads
+//!
+//! The diagrams in `crate::diagram` are built on `Rc`, so they can't be moved to another thread.
+//! The `Diagram` in this module holds its sets and maps in `Arc`s instead, so it can be built once
+//! (e.g. in a `static` `OnceLock`) and checked from several tests or worker threads.
+//! Each check runs on a local view of the diagram, see `Diagram::to_local`, so its results stay on the checking thread.
+//!
+//! ```
+//! use std::sync::OnceLock;
+//! use commuter::diagram::{Set, CommutativeDiagramResult};
+//! use commuter::sync::{self, Diagram, Map};
+//!
+//! fn diagram() -> &'static Diagram {
+//!     static DIAGRAM: OnceLock<Diagram> = OnceLock::new();
+//!     DIAGRAM.get_or_init(|| {
+//!         Diagram::new(
+//!             vec![
+//!                 sync::set(Set::new(vec![(1, 2), (3, 4)])),
+//!                 sync::set(Set::<i32, _, _>::new_no_generating_set()),
+//!             ],
+//!             vec![
+//!                 Map::new(0, 1, |(a, b): &(i32, i32)| a + b, "a + b"),
+//!                 Map::new(0, 1, |(a, b): &(i32, i32)| b + a, "b + a"),
+//!             ],
+//!         )
+//!     })
+//! }
+//!
+//! std::thread::spawn(|| {
+//!     assert!(matches!(
+//!         sync::diagram_commutes(diagram()).unwrap(),
+//!         CommutativeDiagramResult::Commutes { .. }
+//!     ))
+//! })
+//! .join()
+//! .unwrap();
+//! ```
+
+use crate::diagram::{
+    self, CheckObserver, CheckOptions, CommutativeDiagramError, CommutativeDiagramResult,
+    Comparison, DiagramError, Element, ElementType, FallibleMap, Mappable, OnMapError, PartialMap,
+    RelationMap, SetLike, Unmapped, ValueMap,
+};
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// Moves a set (e.g. from `Set::new`) into an `Arc`, so that it can be shared between threads.
+/// This requires every closure of the set (its property, filter, order and so on) to be `Send + Sync`.
+/// The set is only cloned if it is still shared elsewhere.
+pub fn set<S>(set: Rc<S>) -> Arc<dyn SetLike + Send + Sync>
+where
+    S: SetLike + Clone + Send + Sync + 'static,
+{
+    Arc::new(Rc::unwrap_or_clone(set))
+}
+
+impl<S> SetLike for Arc<S>
+where
+    S: SetLike + ?Sized,
+{
+    fn elements(&self) -> Box<dyn Iterator<Item = Rc<dyn Element>>> {
+        (**self).elements()
+    }
+
+    fn check(&self, element: &Rc<dyn Element>) -> bool {
+        (**self).check(element)
+    }
+
+    fn filter(&self, element: &Rc<dyn Element>) -> bool {
+        (**self).filter(element)
+    }
+
+    fn shrink(&self, element: &Rc<dyn Element>) -> Box<dyn Iterator<Item = Rc<dyn Element>>> {
+        (**self).shrink(element)
+    }
+
+    fn seed(&self) -> Option<u64> {
+        (**self).seed()
+    }
+
+    fn element_type(&self) -> Option<ElementType> {
+        (**self).element_type()
+    }
+
+    fn equivalent(&self, a: &Rc<dyn Element>, b: &Rc<dyn Element>) -> bool {
+        (**self).equivalent(a, b)
+    }
+
+    fn normalise(&self, element: &Rc<dyn Element>) -> Rc<dyn Element> {
+        (**self).normalise(element)
+    }
+
+    fn le(&self, a: &Rc<dyn Element>, b: &Rc<dyn Element>) -> bool {
+        (**self).le(a, b)
+    }
+}
+
+impl<M> Mappable for Arc<M>
+where
+    M: Mappable + ?Sized,
+{
+    fn map(&self, key: &Rc<dyn Element>) -> Result<Rc<dyn Element>, Unmapped> {
+        (**self).map(key)
+    }
+
+    fn domain(&self) -> ElementType {
+        (**self).domain()
+    }
+
+    fn codomain(&self) -> ElementType {
+        (**self).codomain()
+    }
+}
+
+/// A map between the sets of a thread-safe diagram, see `diagram::Map`
+pub struct Map {
+    from: usize,
+    to: usize,
+    map: Arc<dyn Mappable + Send + Sync>,
+    name: String,
+}
+
+impl Map {
+    pub fn new<F, U, V>(from: usize, to: usize, map: F, name: &str) -> Map
+    where
+        F: Fn(&U) -> V + Send + Sync + 'static,
+        U: Clone + PartialEq + core::fmt::Debug + 'static,
+        V: Clone + PartialEq + core::fmt::Debug + 'static,
+    {
+        Map {
+            from,
+            to,
+            map: Arc::new(ValueMap::new(map)),
+            name: name.to_owned(),
+        }
+    }
+
+    // See `diagram::Map::new_partial`
+    pub fn new_partial<F, D, U, V>(from: usize, to: usize, map: F, domain: D, name: &str) -> Map
+    where
+        F: Fn(&U) -> V + Send + Sync + 'static,
+        D: Fn(&U) -> bool + Send + Sync + 'static,
+        U: Clone + PartialEq + core::fmt::Debug + 'static,
+        V: Clone + PartialEq + core::fmt::Debug + 'static,
+    {
+        Map {
+            from,
+            to,
+            map: Arc::new(PartialMap::new(map, domain)),
+            name: name.to_owned(),
+        }
+    }
+
+    // See `diagram::Map::new_relation`
+    pub fn new_relation<F, U, V>(from: usize, to: usize, map: F, name: &str) -> Map
+    where
+        F: Fn(&U) -> Vec<V> + Send + Sync + 'static,
+        U: Clone + PartialEq + core::fmt::Debug + 'static,
+        V: Clone + PartialEq + core::fmt::Debug + 'static,
+    {
+        Map {
+            from,
+            to,
+            map: Arc::new(RelationMap::new(map)),
+            name: name.to_owned(),
+        }
+    }
+
+    // See `diagram::Map::new_fallible`
+    pub fn new_fallible<F, U, V, E>(
+        from: usize,
+        to: usize,
+        map: F,
+        name: &str,
+        on_error: OnMapError,
+    ) -> Map
+    where
+        F: Fn(&U) -> Result<V, E> + Send + Sync + 'static,
+        U: Clone + PartialEq + core::fmt::Debug + 'static,
+        V: Clone + PartialEq + core::fmt::Debug + 'static,
+        E: core::fmt::Debug + 'static,
+    {
+        Map {
+            from,
+            to,
+            map: Arc::new(FallibleMap::new(map, on_error)),
+            name: name.to_owned(),
+        }
+    }
+}
+
+/// A diagram that is `Send + Sync`, with the same options as `diagram::Diagram`
+pub struct Diagram {
+    sets: Vec<Arc<dyn SetLike + Send + Sync>>,
+    maps: Vec<Map>,
+    set_names: Vec<String>,
+    set_positions: Vec<Option<(usize, usize)>>,
+    faces: Vec<(Vec<usize>, Vec<usize>, Comparison)>, // Declared pairs of paths as map indices
+}
+
+impl Diagram {
+    pub fn new(sets: Vec<Arc<dyn SetLike + Send + Sync>>, maps: Vec<Map>) -> Diagram {
+        let set_names = (0..sets.len()).map(|ix| ix.to_string()).collect();
+        let set_positions = vec![None; sets.len()];
+        Diagram {
+            sets,
+            maps,
+            set_names,
+            set_positions,
+            faces: Vec::new(),
+        }
+    }
+
+    // See `diagram::Diagram::try_new`
+    pub fn try_new(
+        sets: Vec<Arc<dyn SetLike + Send + Sync>>,
+        maps: Vec<Map>,
+    ) -> Result<Diagram, DiagramError> {
+        let diagram = Diagram::new(sets, maps);
+        diagram.to_local().validate()?;
+        Ok(diagram)
+    }
+
+    pub fn with_set_names(mut self, names: &[&str]) -> Diagram {
+        for (set_name, name) in self.set_names.iter_mut().zip(names) {
+            *set_name = name.to_string();
+        }
+        self
+    }
+
+    pub fn with_set_position(mut self, set: usize, position: (usize, usize)) -> Diagram {
+        if let Some(hint) = self.set_positions.get_mut(set) {
+            *hint = Some(position);
+        }
+        self
+    }
+
+    // See `diagram::Diagram::with_face`
+    pub fn with_face(mut self, left: &[usize], right: &[usize]) -> Result<Diagram, DiagramError> {
+        self.to_local().with_face(left, right)?;
+        self.faces
+            .push((left.to_vec(), right.to_vec(), Comparison::Equal));
+        Ok(self)
+    }
+
+    // See `diagram::Diagram::with_named_face`
+    pub fn with_named_face(self, left: &[&str], right: &[&str]) -> Result<Diagram, DiagramError> {
+        let local = self.to_local();
+        let left = local.map_indices(left)?;
+        let right = local.map_indices(right)?;
+        self.with_face(&left, &right)
+    }
+
+    // See `diagram::Diagram::with_lax_face`
+    pub fn with_lax_face(
+        mut self,
+        lower: &[usize],
+        upper: &[usize],
+    ) -> Result<Diagram, DiagramError> {
+        self.to_local().with_lax_face(lower, upper)?;
+        self.faces
+            .push((lower.to_vec(), upper.to_vec(), Comparison::Below));
+        Ok(self)
+    }
+
+    // See `diagram::Diagram::with_named_lax_face`
+    pub fn with_named_lax_face(
+        self,
+        lower: &[&str],
+        upper: &[&str],
+    ) -> Result<Diagram, DiagramError> {
+        let local = self.to_local();
+        let lower = local.map_indices(lower)?;
+        let upper = local.map_indices(upper)?;
+        self.with_lax_face(&lower, &upper)
+    }
+
+    // A view of this diagram for the current thread, sharing its sets and maps.
+    // This is cheap compared to checking it, and can also be used to render the diagram
+    pub fn to_local(&self) -> diagram::Diagram {
+        let sets = self
+            .sets
+            .iter()
+            .map(|set| Rc::new(set.clone()) as Rc<dyn SetLike>)
+            .collect();
+        let maps = self
+            .maps
+            .iter()
+            .map(|map| diagram::Map {
+                from: map.from,
+                to: map.to,
+                map: Rc::new(map.map.clone()),
+                name: map.name.clone(),
+            })
+            .collect();
+        let set_names: Vec<&str> = self.set_names.iter().map(String::as_str).collect();
+
+        let mut local = diagram::Diagram::new(sets, maps).with_set_names(&set_names);
+        for (set, position) in self.set_positions.iter().enumerate() {
+            if let Some(position) = position {
+                local = local.with_set_position(set, *position);
+            }
+        }
+        for (left, right, comparison) in self.faces.iter() {
+            local = match comparison {
+                Comparison::Equal => local.with_face(left, right),
+                Comparison::Below => local.with_lax_face(left, right),
+            }
+            .expect("Faces are validated when they are added");
+        }
+        local
+    }
+}
+
+pub fn diagram_commutes(
+    diagram: &Diagram,
+) -> Result<CommutativeDiagramResult, CommutativeDiagramError> {
+    diagram::diagram_commutes(&diagram.to_local())
+}
+
+pub fn diagram_commutes_bounded(
+    diagram: &Diagram,
+    max_path_length: usize,
+) -> Result<CommutativeDiagramResult, CommutativeDiagramError> {
+    diagram::diagram_commutes_bounded(&diagram.to_local(), max_path_length)
+}
+
+pub fn diagram_faces_commute(
+    diagram: &Diagram,
+) -> Result<CommutativeDiagramResult, CommutativeDiagramError> {
+    diagram::diagram_faces_commute(&diagram.to_local())
+}
+
+pub fn diagram_commutes_with(
+    diagram: &Diagram,
+    options: &CheckOptions,
+) -> Result<CommutativeDiagramResult, CommutativeDiagramError> {
+    diagram::diagram_commutes_with(&diagram.to_local(), options)
+}
+
+pub fn diagram_commutes_observed(
+    diagram: &Diagram,
+    options: &CheckOptions,
+    observer: &mut dyn CheckObserver,
+) -> Result<CommutativeDiagramResult, CommutativeDiagramError> {
+    diagram::diagram_commutes_observed(&diagram.to_local(), options, observer)
+}
+
+/// Checks commutativity with the given options, splitting the source elements between the threads of the rayon pool.
+/// All threads stop as soon as enough counterexamples were found between them,
+/// which are then shrunk (and returned) on the calling thread.
+#[cfg(feature = "rayon")]
+pub fn diagram_commutes_parallel(
+    diagram: &Diagram,
+    options: &CheckOptions,
+) -> Result<CommutativeDiagramResult, CommutativeDiagramError> {
+    use crate::diagram::{Partition, SharedProgress, Stopped};
+    use rayon::prelude::*;
+    use std::sync::atomic::Ordering;
+
+    // What a worker found. Elements are local to the worker's thread, so counterexamples are sent back by index.
+    // A worker that stopped early without finding any counterexample reports why
+    enum Found {
+        Commutes(Vec<usize>, Option<Stopped>),
+        DoesNotCommute(Vec<(usize, Vec<usize>, Vec<usize>, Comparison)>),
+        Failed(String),
+    }
+
+    let count = rayon::current_num_threads();
+    let shared = SharedProgress::default();
+    // The element budget is shared evenly between the workers
+    let worker_options = CheckOptions {
+        shrink: false,
+        element_budget: options.element_budget.map(|budget| budget.div_ceil(count)),
+        ..options.clone()
+    };
+
+    let found: Vec<Found> = (0..count)
+        .into_par_iter()
+        .map(|index| {
+            let partition = Partition {
+                index,
+                count,
+                shared: Some(&shared),
+            };
+            match diagram::check(&diagram.to_local(), &worker_options, partition, &mut ()) {
+                Ok(CommutativeDiagramResult::Commutes {
+                    checked_elements, ..
+                }) => Found::Commutes(checked_elements, None),
+                Ok(CommutativeDiagramResult::Incomplete {
+                    stopped,
+                    checked_elements,
+                    ..
+                }) => Found::Commutes(checked_elements, Some(stopped)),
+                Ok(CommutativeDiagramResult::DoesNotCommute(counterexamples)) => {
+                    Found::DoesNotCommute(
+                        counterexamples
+                            .into_iter()
+                            .map(|counterexample| {
+                                (
+                                    counterexample.element_ix,
+                                    counterexample.left.maps,
+                                    counterexample.right.maps,
+                                    counterexample.comparison,
+                                )
+                            })
+                            .collect(),
+                    )
+                }
+                Err(error) => {
+                    shared.failed.store(true, Ordering::Relaxed);
+                    Found::Failed(error.to_string())
+                }
+            }
+        })
+        .collect();
+
+    let local = diagram.to_local();
+
+    // Errors can't be sent between threads either, so the failing share of the check is repeated here to report it.
+    // The repeat has no budgets or cap on counterexamples, so that it gets at least as far as the worker did
+    let replay_options = CheckOptions {
+        max_counterexamples: None,
+        time_budget: None,
+        element_budget: None,
+        ..worker_options.clone()
+    };
+    for (index, found) in found.iter().enumerate() {
+        if let Found::Failed(error) = found {
+            let partition = Partition {
+                index,
+                count,
+                shared: None,
+            };
+            diagram::check(&local, &replay_options, partition, &mut ())?;
+
+            // The sets or maps behave differently on this thread, so only the worker's description of the error is left
+            return Err(CommutativeDiagramError::WorkerError(error.clone()));
+        }
+    }
+
+    let mut counterexamples = Vec::new();
+    let mut checked_elements = vec![0; local.sets.len()];
+    let mut stopped = None;
+    for found in found {
+        match found {
+            Found::Commutes(checked, worker_stopped) => {
+                stopped = stopped.or(worker_stopped);
+                for (total, checked) in checked_elements.iter_mut().zip(checked) {
+                    *total += checked;
+                }
+            }
+            Found::DoesNotCommute(found) => {
+                for (element_ix, left, right, comparison) in found {
+                    match diagram::replay_counterexample(
+                        &local,
+                        element_ix,
+                        (&left, &right),
+                        comparison,
+                        options,
+                    ) {
+                        Some(counterexample) => counterexamples.push(counterexample),
+                        // As for errors, the sets or maps behave differently on this thread, so the counterexample is lost
+                        None => {
+                            let describe = |path: &[usize]| {
+                                path.iter()
+                                    .map(|map| local.maps[*map].name.as_str())
+                                    .collect::<Vec<&str>>()
+                                    .join(" -> ")
+                            };
+                            return Err(CommutativeDiagramError::WorkerError(format!(
+                                "{} and {} failed on element {} of their source in a worker, but not when repeated",
+                                describe(&left),
+                                describe(&right),
+                                element_ix
+                            )));
+                        }
+                    }
+                }
+            }
+            Found::Failed(_) => unreachable!("Failed workers are reported above"),
+        }
+    }
+
+    if let Some(max_counterexamples) = options.max_counterexamples {
+        counterexamples.truncate(max_counterexamples);
+    }
+    if !counterexamples.is_empty() {
+        return Ok(CommutativeDiagramResult::DoesNotCommute(counterexamples));
+    }
+
+    let path_length_bound = options.max_path_length;
+    let seeds = local.sets.iter().map(|set| set.seed()).collect();
+    let set_names = diagram.set_names.clone();
+    Ok(match stopped {
+        Some(stopped) => CommutativeDiagramResult::Incomplete {
+            stopped,
+            checked_elements,
+            path_length_bound,
+            seeds,
+            set_names,
+        },
+        None => CommutativeDiagramResult::Commutes {
+            checked_elements,
+            path_length_bound,
+            seeds,
+            set_names,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagram::Set;
+    use std::sync::OnceLock;
+
+    static DIAGRAM: OnceLock<Diagram> = OnceLock::new();
+
+    fn diagram() -> &'static Diagram {
+        DIAGRAM.get_or_init(|| {
+            Diagram::try_new(
+                vec![
+                    set(Set::new((-50..50).collect::<Vec<i32>>())),
+                    set(Set::<i32, _, _>::new_no_generating_set()),
+                ],
+                vec![
+                    Map::new(0, 1, |x: &i32| x * x, "square"),
+                    Map::new(0, 1, |x: &i32| x.abs() * x.abs(), "abs . square"),
+                    Map::new_partial(0, 1, |x: &i32| x * x * x / x, |x: &i32| *x != 0, "cube / x"),
+                ],
+            )
+            .unwrap()
+            .with_set_names(&["Z", "N"])
+        })
+    }
+
+    #[test]
+    fn test_diagram_checked_from_several_threads() {
+        std::thread::scope(|scope| {
+            let checks: Vec<_> = (0..4)
+                .map(|_| {
+                    scope.spawn(|| {
+                        matches!(
+                            diagram_commutes(diagram()).unwrap(),
+                            CommutativeDiagramResult::Commutes { .. }
+                        )
+                    })
+                })
+                .collect();
+
+            for check in checks {
+                assert!(check.join().unwrap());
+            }
+        });
+
+        assert!(diagram()
+            .to_local()
+            .to_text(None)
+            .contains("Z --square--> N"));
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_parallel_check_reports_errors_of_workers() {
+        // This map only fails the first time it sees 50, so repeating the check doesn't reproduce the error
+        let failed = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let diagram = Diagram::new(
+            vec![
+                set(Set::new((0..100).collect::<Vec<i32>>())),
+                set(Set::<i32, _, _>::new_no_generating_set()),
+            ],
+            vec![
+                Map::new(0, 1, |x: &i32| *x, "id"),
+                Map::new_fallible(
+                    0,
+                    1,
+                    move |x: &i32| {
+                        if *x == 50 && !failed.swap(true, std::sync::atomic::Ordering::Relaxed) {
+                            Err("flaky")
+                        } else {
+                            Ok(*x)
+                        }
+                    },
+                    "flaky",
+                    OnMapError::Report,
+                ),
+            ],
+        );
+
+        match diagram_commutes_parallel(&diagram, &CheckOptions::default()) {
+            Err(CommutativeDiagramError::WorkerError(error)) => assert!(error.contains("flaky")),
+            _ => panic!("A worker failed"),
+        }
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_parallel_check_reports_counterexamples_of_workers() {
+        // This map only disagrees the first time it sees 50, so repeating the check doesn't reproduce the counterexample
+        let disagreed = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let diagram = Diagram::new(
+            vec![
+                set(Set::new((0..100).collect::<Vec<i32>>())),
+                set(Set::<i32, _, _>::new_no_generating_set()),
+            ],
+            vec![
+                Map::new(0, 1, |x: &i32| *x, "id"),
+                Map::new(
+                    0,
+                    1,
+                    move |x: &i32| {
+                        if *x == 50 && !disagreed.swap(true, std::sync::atomic::Ordering::Relaxed) {
+                            0
+                        } else {
+                            *x
+                        }
+                    },
+                    "flaky",
+                ),
+            ],
+        );
+
+        match diagram_commutes_parallel(&diagram, &CheckOptions::default()) {
+            Err(CommutativeDiagramError::WorkerError(error)) => {
+                assert!(error.starts_with("id and flaky failed on element 50"))
+            }
+            _ => panic!("A worker found a counterexample"),
+        }
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_parallel_check_agrees_with_sequential_check() {
+        let options = CheckOptions::default();
+        let sequential = diagram_commutes_with(diagram(), &options).unwrap();
+        let parallel = diagram_commutes_parallel(diagram(), &options).unwrap();
+        match (sequential, parallel) {
+            (
+                CommutativeDiagramResult::Commutes {
+                    checked_elements: sequential,
+                    ..
+                },
+                CommutativeDiagramResult::Commutes {
+                    checked_elements: parallel,
+                    ..
+                },
+            ) => assert_eq!(sequential, parallel),
+            _ => panic!("Both checks should find that the diagram commutes"),
+        }
+
+        // Whichever worker finds a counterexample first, it is shrunk to the same one
+        let failing =
+            Diagram::new(
+                vec![
+                    set(Set::new((0..10000).rev().collect::<Vec<i32>>())
+                        .with_shrinker(|x: &i32| if *x > 0 { vec![x / 2, x - 1] } else { vec![] })),
+                    set(Set::<i32, _, _>::new_no_generating_set()),
+                ],
+                vec![
+                    Map::new(0, 1, |x: &i32| *x, "id"),
+                    Map::new(0, 1, |x: &i32| if *x < 37 { *x } else { 0 }, "truncate"),
+                ],
+            );
+
+        match diagram_commutes_parallel(&failing, &options).unwrap() {
+            CommutativeDiagramResult::Commutes { .. }
+            | CommutativeDiagramResult::Incomplete { .. } => {
+                panic!("truncate is not the identity")
+            }
+            CommutativeDiagramResult::DoesNotCommute(counterexamples) => {
+                assert_eq!(counterexamples.len(), 1);
+                assert_eq!(
+                    counterexamples[0].element.as_any().downcast_ref::<i32>(),
+                    Some(&37)
+                );
+            }
+        }
+    }
+}